@@ -0,0 +1,51 @@
+//! Benchmarks for the most expensive operations of the bridge. These use the same
+//! test kernel as the integration tests, which is downloaded on first use.
+//! Run with `cargo bench`.
+use autokernel::{
+    bridge::Bridge,
+    script::{KConfig, LuaScript, Script},
+};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+#[allow(dead_code)]
+#[path = "../tests/setup_teardown.rs"]
+mod setup_teardown;
+
+fn bench_bridge(c: &mut Criterion) {
+    let bridge = setup_teardown::setup();
+    let kernel_dir = bridge.kernel_dir.clone();
+
+    let mut group = c.benchmark_group("bridge");
+    group.sample_size(10);
+    // The bridge itself is only compiled once, so this measures loading and parsing the Kconfig files
+    group.bench_function("new", |b| b.iter(|| Bridge::new(kernel_dir.clone(), None).unwrap()));
+    group.bench_function("recalculate_all_symbols", |b| {
+        b.iter(|| bridge.recalculate_all_symbols())
+    });
+    group.finish();
+}
+
+fn bench_scripts(c: &mut Criterion) {
+    let bridge = setup_teardown::setup();
+    let kconfig = KConfig::from_content("good.kconfig".into(), include_str!("../tests/good.kconfig").into()).unwrap();
+    let lua = LuaScript::from_raw("good.lua".into(), include_str!("../tests/good.lua").into()).unwrap();
+
+    let mut group = c.benchmark_group("apply");
+    group.sample_size(10);
+    group.bench_function("kconfig", |b| {
+        b.iter(|| {
+            kconfig.apply(&bridge).unwrap();
+            bridge.history.borrow_mut().clear();
+        })
+    });
+    group.bench_function("lua", |b| {
+        b.iter(|| {
+            lua.apply(&bridge).unwrap();
+            bridge.history.borrow_mut().clear();
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_bridge, bench_scripts);
+criterion_main!(benches);
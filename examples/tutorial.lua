@@ -62,6 +62,41 @@ ACPI "y"  -- Using a string value
 DEFAULT_HOSTNAME "my_hostname" -- <- A string symbol
 PHYSICAL_ALIGN(0x11223344)     -- <- A hex symbol
 
+-- String symbols can also be extended instead of overwritten, which is useful when
+-- several config files each contribute a part of the value. A separator (default " ")
+-- is inserted, unless the symbol was empty before. Pass "" to concatenate directly.
+CMDLINE:append "quiet"
+CMDLINE:append("loglevel=3")
+
+-- For the kernel command line there are dedicated helpers, which treat it as a list of
+-- parameters. Adding a key=value parameter replaces an existing parameter with the same key.
+ak.cmdline_add "root=/dev/sda1"
+ak.cmdline_remove "debug"
+if ak.cmdline_has "quiet" then
+	ak.cmdline_add "loglevel=0"
+end
+
+-- Some features may or may not be available depending on the architecture or other
+-- choices. To set such symbols only if they can currently be changed, and skip them
+-- silently otherwise, use set_if_visible. It returns whether the value was set.
+X86_X2APIC:set_if_visible "y"
+if not WLAN_VENDOR_REALTEK:is_visible() then
+	print("realtek drivers are unavailable")
+end
+
+-- Referencing a symbol that doesn't exist in the current kernel is an error. Scripts that
+-- target several kernel versions can check whether a symbol exists beforehand.
+if ak.symbol_exists "ZSWAP_DEFAULT_ON" then
+	ZSWAP_DEFAULT_ON "y"
+end
+
+-- When composing a config from several profiles, a base profile can set symbols weakly.
+-- Weak assignments are skipped if the symbol was already set explicitly, and any later
+-- explicit assignment overrides them without being reported as a reassignment.
+ak.symbol_set_weak("USB_STORAGE", m)
+USB_STORAGE:set_weak "m"
+USB_STORAGE "y" -- <- overrides the weak assignments above
+
 -- Invalid assignments will always cause errors, but errors are reported "late",
 -- so evaluation continues even if an assignment failed. Most symbol assignments don't depend
 -- on each other, so this allows autokernel to show you all errors at once.
@@ -153,6 +188,59 @@ RTLWIFI_USB:satisfy { y, recursive = true }
 -- If you prefer to use modules where possible, you can solve for `m` instead:
 RTLWIFI_USB:satisfy { m, recursive = true }
 
+-- If you need to inspect the dependencies of a symbol yourself, you can query them
+-- as a nested table. Each node has an `op` field, which is one of "and", "or" (with
+-- `left` and `right`), "not" (with `expr`), "eq", "neq", "lth", "leq", "gth", "geq"
+-- (comparing `left` and `right`), "sym" (with `name`) or "const" (with `value`).
+--
+-- local function print_deps(e, indent)
+--   indent = indent or ""
+--   if e.op == "sym" then print(indent .. e.name)
+--   elseif e.op == "const" then print(indent .. tostring(e.value))
+--   elseif e.op == "not" then print(indent .. "not") print_deps(e.expr, indent .. "  ")
+--   else print(indent .. e.op) print_deps(e.left, indent .. "  ") print_deps(e.right, indent .. "  ")
+--   end
+-- end
+-- print_deps(RTLWIFI_USB:deps())
+
+--###############################################################
+-- Temporary assignments
+--###############################################################
+
+-- To inspect a hypothetical state, symbols can be set temporarily for the duration
+-- of a function. Afterwards, everything is restored, including any assignments made
+-- inside the function. The return value of the function is passed through.
+local realtek_available = ak.with({ WLAN = y }, function()
+	return WLAN_VENDOR_REALTEK:is_visible()
+end)
+
+--###############################################################
+-- Aliases
+--###############################################################
+
+-- Groups of assignments that belong together can be bundled into an alias,
+-- which can then be called like a function. Assignments inside the alias are
+-- reported at their location in the alias definition, and errors raised inside
+-- an alias mention both where it was defined and where it was called.
+ak.define_alias("ENABLE_CONTAINERS", function()
+	NAMESPACES "y"
+	CGROUPS "y"
+	OVERLAY_FS "y"
+end)
+
+ENABLE_CONTAINERS()
+
+-- When writing your own helper functions, assignments are normally reported at their
+-- location inside the helper. To report them at the call site instead, use ak.set_at
+-- which accepts an explicit file and line.
+local function enable_all(names)
+	local info = debug.getinfo(2, "Sl")
+	for _, name in ipairs(names) do
+		ak.set_at(name, y, info.source, info.currentline)
+	end
+end
+enable_all { "NAMESPACES", "CGROUPS" }
+
 -- Finally, you are of course able to use lua to it's full extent. Feel free to call
 -- other programs, read/write files, make web requests or anything else that you
 -- require to build the perfect kernel config.
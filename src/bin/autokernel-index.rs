@@ -1,7 +1,13 @@
-use autokernel::bridge::{Bridge, Symbol};
+use autokernel::bridge::types::CSymbol;
+use autokernel::bridge::{Bridge, Expr, Symbol, Terminal};
+use autokernel::index::{
+    config_values, create_schema, index_values, indexed_kernel_id, is_valid_symbol, kernel_name, parse_kernel_version,
+    print_matrix, MatrixEntry,
+};
 use rusqlite::{Connection, Transaction};
 use uuid::Uuid;
 
+use std::collections::BTreeMap;
 use std::io::{self, Write};
 use std::path::PathBuf;
 use std::process::{Command, Stdio};
@@ -45,6 +51,42 @@ struct ActionValues {
     kconf: PathBuf,
 }
 
+#[derive(Debug, clap::Args)]
+struct ActionChurn {
+    /// Compare against the most recent indexed kernel at or before this version,
+    /// instead of the kernel indexed right before the latest one.
+    #[clap(long, value_name = "VERSION")]
+    since: Option<String>,
+
+    /// Only show symbols that were removed
+    #[clap(long, conflicts_with = "only_added")]
+    only_removed: bool,
+
+    /// Only show symbols that were added
+    #[clap(long)]
+    only_added: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct ActionDefaultChanges {
+    /// The older kernel, given by its version or name
+    #[clap(long, value_name = "KERNEL")]
+    from: String,
+
+    /// The newer kernel, given by its version or name
+    #[clap(long, value_name = "KERNEL")]
+    to: String,
+}
+
+#[derive(Debug, clap::Args)]
+struct ActionMatrix {
+    /// Only compare indexed configs with this name (as given to the values action).
+    /// Can be given multiple times. By default, all configs except the kernel's
+    /// defaults and defconfig are compared.
+    #[clap(short, long = "name", value_name = "NAME")]
+    names: Vec<String>,
+}
+
 #[derive(Debug, clap::Subcommand)]
 enum Action {
     /// Just initialize the database schema
@@ -53,6 +95,13 @@ enum Action {
     Kernel,
     /// Index symbol values
     Values(ActionValues),
+    /// Show which symbols were added, removed or changed between the latest indexed kernel and an older one
+    Churn(ActionChurn),
+    /// Show symbols whose default value differs between two indexed kernels
+    DefaultChanges(ActionDefaultChanges),
+    /// Compare the symbol values of indexed configs across kernels and architectures,
+    /// and show all symbols whose value differs between them
+    Matrix(ActionMatrix),
 }
 
 fn main() -> Result<()> {
@@ -68,7 +117,7 @@ fn main() -> Result<()> {
             let mut conn = Connection::open(&args.db)?;
             create_schema(&mut conn)?;
 
-            let kernel_name = bridge.get_env("PWD").unwrap().split("/").last().unwrap().to_string();
+            let kernel_name = kernel_name(&bridge)?;
             let (v_major, v_minor, v_patch) = parse_kernel_version(&bridge.get_env("KERNELVERSION").unwrap())?;
             let kernel_id = Uuid::new_v4().to_string();
             let tx = conn.transaction()?;
@@ -104,13 +153,7 @@ fn main() -> Result<()> {
             let mut conn = Connection::open(&args.db)?;
             create_schema(&mut conn)?;
 
-            let kernel_name = bridge.get_env("PWD").unwrap().split("/").last().unwrap().to_string();
-            let (v_major, v_minor, v_patch) = parse_kernel_version(&bridge.get_env("KERNELVERSION").unwrap())?;
-            let kernel_id: String = conn
-                .prepare(
-                    "SELECT id from kernel WHERE version_major=? AND version_minor=? AND version_patch=? AND name=?",
-                )?
-                .query_row((v_major, v_minor, v_patch, &kernel_name), |row| row.get(0))?;
+            let kernel_id = indexed_kernel_id(&conn, &bridge)?;
             let tx = conn.transaction()?;
 
             index_values(
@@ -123,73 +166,255 @@ fn main() -> Result<()> {
             )?;
             tx.commit()?;
         }
+        Action::Churn(action) => {
+            let conn = Connection::open(&args.db)?;
+            churn(&conn, action)?;
+        }
+        Action::DefaultChanges(action) => {
+            let conn = Connection::open(&args.db)?;
+            default_changes(&conn, action)?;
+        }
+        Action::Matrix(action) => {
+            let conn = Connection::open(&args.db)?;
+            matrix(&conn, action)?;
+        }
     };
 
     Ok(())
 }
 
-fn create_schema(conn: &mut Connection) -> Result<()> {
-    let tx = conn.transaction()?;
-    // Create tables
-    tx.execute(
-        "CREATE TABLE IF NOT EXISTS kernel (
-            id               TEXT NOT NULL,
-            version_major    INTEGER NOT NULL,
-            version_minor    INTEGER NOT NULL,
-            version_patch    INTEGER NOT NULL,
-            name             TEXT NOT NULL,
-            UNIQUE (version_major, version_minor, version_patch, name),
-            PRIMARY KEY (id))",
-        (), // empty list of parameters.
-    )?;
-    tx.execute(
-        "CREATE TABLE IF NOT EXISTS config (
-            id               TEXT NOT NULL,
-            kernel_id        TEXT NOT NULL REFERENCES kernel(id),
-            architecture     TEXT,
-            name             TEXT NOT NULL,
-            UNIQUE (kernel_id, architecture, name),
-            PRIMARY KEY (id))",
-        (), // empty list of parameters.
-    )?;
-    tx.execute(
-        "CREATE TABLE IF NOT EXISTS symbol (
-            kernel_id        TEXT NOT NULL REFERENCES kernel(id),
-            name             TEXT NOT NULL,
-            type             TEXT NOT NULL,
-            visibility_expression TEXT,
-            reverse_dependencies  TEXT,
-            PRIMARY KEY (kernel_id, name))",
-        (), // empty list of parameters.
-    )?;
-    tx.execute(
-        "CREATE TABLE IF NOT EXISTS value (
-            config_id        TEXT NOT NULL REFERENCES config(id),
-            symbol_name      TEXT NOT NULL,
-            value            TEXT NOT NULL,
-            PRIMARY KEY (config_id, symbol_name))",
-        (), // empty list of parameters.
+struct IndexedKernel {
+    id: String,
+    version: (u32, u32, u32),
+    name: String,
+}
+
+/// The indexed information about a symbol which is compared between kernels
+#[derive(PartialEq)]
+struct IndexedSymbol {
+    symbol_type: String,
+    visibility_expression: Option<String>,
+    reverse_dependencies: Option<String>,
+}
+
+fn indexed_symbols(conn: &Connection, kernel_id: &str) -> Result<BTreeMap<String, IndexedSymbol>> {
+    let mut stmt =
+        conn.prepare("SELECT name, type, visibility_expression, reverse_dependencies FROM symbol WHERE kernel_id=?")?;
+    let symbols = stmt
+        .query_map([kernel_id], |row| {
+            rusqlite::Result::Ok((
+                row.get(0)?,
+                IndexedSymbol {
+                    symbol_type: row.get(1)?,
+                    visibility_expression: row.get(2)?,
+                    reverse_dependencies: row.get(3)?,
+                },
+            ))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(symbols)
+}
+
+impl IndexedKernel {
+    fn display_name(&self) -> String {
+        format!(
+            "{}.{}.{} ({})",
+            self.version.0, self.version.1, self.version.2, self.name
+        )
+    }
+}
+
+/// Returns all indexed kernels, sorted by version
+fn indexed_kernels(conn: &Connection) -> Result<Vec<IndexedKernel>> {
+    let mut stmt = conn.prepare("SELECT id, version_major, version_minor, version_patch, name FROM kernel")?;
+    let mut kernels = stmt
+        .query_map((), |row| {
+            rusqlite::Result::Ok(IndexedKernel {
+                id: row.get(0)?,
+                version: (row.get(1)?, row.get(2)?, row.get(3)?),
+                name: row.get(4)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    kernels.sort_by_key(|k| k.version);
+    Ok(kernels)
+}
+
+/// Finds the single indexed kernel with the given name or version
+fn find_kernel(kernels: Vec<IndexedKernel>, spec: &str) -> Result<IndexedKernel> {
+    let version = parse_kernel_version(spec).ok();
+    let mut matches = kernels
+        .into_iter()
+        .filter(|k| k.name == spec || Some(k.version) == version)
+        .collect::<Vec<_>>();
+    match matches.len() {
+        0 => bail!("No indexed kernel matches '{}'", spec),
+        1 => Ok(matches.remove(0)),
+        _ => bail!(
+            "Several indexed kernels match '{}', please use one of their names: {}",
+            spec,
+            matches.iter().map(|k| k.name.as_str()).collect::<Vec<_>>().join(", ")
+        ),
+    }
+}
+
+/// Returns the default values of all symbols of the given kernel
+fn default_values(conn: &Connection, kernel_id: &str) -> Result<BTreeMap<String, String>> {
+    let mut stmt = conn.prepare(
+        "SELECT value.symbol_name, value.value FROM value
+            JOIN config ON value.config_id = config.id
+            WHERE config.kernel_id = ? AND config.name = 'defaults'",
     )?;
-    tx.commit()?;
-    Ok(())
+    let values = stmt
+        .query_map([kernel_id], |row| rusqlite::Result::Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(values)
 }
 
-fn is_valid_symbol(symbol: &Symbol) -> bool {
-    return !symbol.is_const() && symbol.name().is_some();
+fn matrix(conn: &Connection, action: &ActionMatrix) -> Result<()> {
+    let kernels = indexed_kernels(conn)?;
+    let mut stmt = conn.prepare("SELECT id, kernel_id, architecture, name FROM config")?;
+    let configs = stmt
+        .query_map((), |row| {
+            rusqlite::Result::Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut entries = Vec::new();
+    // Iterate kernels first, so entries are sorted by kernel version
+    for kernel in &kernels {
+        for (config_id, _, arch, name) in configs.iter().filter(|c| c.1 == kernel.id) {
+            let selected = if action.names.is_empty() {
+                name != "defaults" && name != "defconfig"
+            } else {
+                action.names.contains(name)
+            };
+            if selected {
+                entries.push(MatrixEntry {
+                    label: format!(
+                        "{} {} [{}]",
+                        name,
+                        kernel.display_name(),
+                        arch.as_deref().unwrap_or("default arch")
+                    ),
+                    values: config_values(conn, config_id)?,
+                });
+            }
+        }
+    }
+    print_matrix(&entries)
 }
 
-fn parse_kernel_version(ver: &str) -> Result<(u32, u32, u32)> {
-    if let Some(d1) = ver.find('.') {
-        let major = ver[..d1].parse::<u32>()?;
-        let rest = &ver[d1 + 1..];
-        if let Some(d2) = rest.find('.') {
-            Ok((major, rest[..d2].parse::<u32>()?, rest[d2 + 1..].parse::<u32>()?))
-        } else {
-            Ok((major, rest.parse::<u32>()?, 0))
+fn default_changes(conn: &Connection, action: &ActionDefaultChanges) -> Result<()> {
+    let from = find_kernel(indexed_kernels(conn)?, &action.from)?;
+    let to = find_kernel(indexed_kernels(conn)?, &action.to)?;
+    let before = default_values(conn, &from.id)?;
+    let after = default_values(conn, &to.id)?;
+    println!(
+        "Default changes from {} to {}:",
+        from.display_name().blue(),
+        to.display_name().blue()
+    );
+
+    let mut n_changed = 0;
+    for (name, old) in &before {
+        // Added and removed symbols are reported by churn instead
+        let Some(new) = after.get(name) else {
+            continue;
+        };
+        if old != new {
+            n_changed += 1;
+            println!("  {} {:?} -> {:?}", name, old, new);
         }
-    } else {
-        bail!("Cannot parse kernel version (missing .)");
     }
+
+    println!("{:>12} {} defaults changed", "Summary".green(), n_changed);
+    Ok(())
+}
+
+fn churn(conn: &Connection, action: &ActionChurn) -> Result<()> {
+    let kernels = indexed_kernels(conn)?;
+
+    let Some((latest, older)) = kernels.split_last() else {
+        bail!("No kernels have been indexed yet");
+    };
+    let base =
+        match &action.since {
+            Some(since) => {
+                let since = parse_kernel_version(since)?;
+                older.iter().rev().find(|k| k.version <= since).with_context(|| {
+                    format!("No kernel at or before {}.{}.{} was indexed", since.0, since.1, since.2)
+                })?
+            }
+            None => older
+                .last()
+                .context("At least two kernels must be indexed to compute churn")?,
+        };
+
+    let before = indexed_symbols(conn, &base.id)?;
+    let after = indexed_symbols(conn, &latest.id)?;
+    println!(
+        "Churn from {} to {}:",
+        base.display_name().blue(),
+        latest.display_name().blue()
+    );
+
+    let added = after
+        .keys()
+        .filter(|name| !before.contains_key(*name))
+        .collect::<Vec<_>>();
+    let removed = before
+        .keys()
+        .filter(|name| !after.contains_key(*name))
+        .collect::<Vec<_>>();
+    if !action.only_removed {
+        for name in &added {
+            println!("  {} {} [{}]", "+".green(), name, after[*name].symbol_type);
+        }
+    }
+    if !action.only_added {
+        for name in &removed {
+            println!("  {} {} [{}]", "-".red(), name, before[*name].symbol_type);
+        }
+    }
+
+    let mut n_changed = 0;
+    for (name, old) in &before {
+        let Some(new) = after.get(name) else {
+            continue;
+        };
+        if old == new {
+            continue;
+        }
+        n_changed += 1;
+        if !action.only_added && !action.only_removed {
+            let mut changes = Vec::new();
+            if old.symbol_type != new.symbol_type {
+                changes.push(format!("type {} -> {}", old.symbol_type, new.symbol_type));
+            }
+            if old.visibility_expression != new.visibility_expression {
+                changes.push("dependencies".to_string());
+            }
+            if old.reverse_dependencies != new.reverse_dependencies {
+                changes.push("reverse dependencies".to_string());
+            }
+            println!("  {} {} ({})", "~".yellow(), name, changes.join(", "));
+        }
+    }
+
+    println!(
+        "{:>12} {} added, {} removed, {} changed",
+        "Summary".green(),
+        added.len(),
+        removed.len(),
+        n_changed
+    );
+    Ok(())
 }
 
 fn index_kernel(bridge: &Bridge, tx: &Transaction, kernel_id: &str) -> Result<()> {
@@ -199,10 +424,12 @@ fn index_kernel(bridge: &Bridge, tx: &Transaction, kernel_id: &str) -> Result<()
     let time_start = Instant::now();
     colored::control::set_override(false);
     let mut n_indexed_symbols = 0;
+    let mut n_indexed_dependencies = 0;
     for symbol in &bridge.symbols {
         let symbol = bridge.wrap_symbol(*symbol);
         if is_valid_symbol(&symbol) {
             n_indexed_symbols += 1;
+            n_indexed_dependencies += index_dependencies(bridge, tx, kernel_id, &symbol)?;
 
             tx.execute(
                 "INSERT INTO symbol VALUES (?1, ?2, ?3, ?4, ?5)",
@@ -225,60 +452,51 @@ fn index_kernel(bridge: &Bridge, tx: &Transaction, kernel_id: &str) -> Result<()
     colored::control::unset_override();
 
     println!(
-        "{:>12} kernel [{} symbols] in {:.2?}",
+        "{:>12} kernel [{} symbols, {} dependencies] in {:.2?}",
         "Indexed".green(),
         n_indexed_symbols,
+        n_indexed_dependencies,
         time_start.elapsed()
     );
     Ok(())
 }
 
-fn index_values(
-    bridge: &Bridge,
-    tx: &Transaction,
-    kernel_id: &str,
-    name: &str,
-    kconf: Option<&PathBuf>,
-    arch: Option<&String>,
-) -> Result<()> {
-    if let Some(kconf) = kconf {
-        bridge.read_config_unchecked(kconf)?;
-        println!("{:>12} kconf ({})", "Loaded".green(), kconf.display());
-    }
-
-    let time_start = Instant::now();
-    let config_id = Uuid::new_v4().to_string();
-    tx.execute(
-        "INSERT INTO config VALUES (?1, ?2, ?3, ?4)",
-        (&config_id, kernel_id, arch, name),
-    )?;
-
-    print!("{:>12} symbol values...\r", "Indexing".cyan());
-    io::stdout().flush()?;
+/// Returns the symbols that select (or imply) a symbol, given its reverse dependency
+/// (or implied) expression. Each clause of these expressions has the form `A && cond`,
+/// where A is the selecting symbol and cond the condition of the select statement.
+fn selecting_symbols(expr: &Expr) -> Vec<*mut CSymbol> {
+    expr.or_clauses()
+        .into_iter()
+        .filter_map(|clause| match clause.and_clauses()[0] {
+            Expr::Terminal(Terminal::Symbol(s)) => Some(*s),
+            _ => None,
+        })
+        .collect()
+}
 
-    let mut n_indexed_symbols = 0;
-    for symbol in &bridge.symbols {
-        let symbol = bridge.wrap_symbol(*symbol);
-        if is_valid_symbol(&symbol) {
-            n_indexed_symbols += 1;
+fn index_dependencies(bridge: &Bridge, tx: &Transaction, kernel_id: &str, symbol: &Symbol) -> Result<usize> {
+    let name = symbol.name().unwrap().to_string();
+    let mut edges = Vec::new();
+    if let Some(expr) = symbol.visibility_expression_bare()? {
+        edges.extend(expr.referenced_symbols().into_iter().map(|s| (s, "depends")));
+    }
+    if let Some(expr) = symbol.reverse_dependencies_bare()? {
+        edges.extend(selecting_symbols(&expr).into_iter().map(|s| (s, "select")));
+    }
+    if let Some(expr) = symbol.implied_expression_bare()? {
+        edges.extend(selecting_symbols(&expr).into_iter().map(|s| (s, "imply")));
+    }
 
-            tx.execute(
-                "INSERT INTO value VALUES (?1, ?2, ?3)",
-                (
-                    &config_id,
-                    symbol.name().unwrap().to_string(),
-                    symbol.get_string_value(),
-                ),
-            )?;
+    let mut n_edges = 0;
+    for (referenced, relation) in edges {
+        let referenced = bridge.wrap_symbol(referenced);
+        if !is_valid_symbol(&referenced) {
+            continue;
         }
+        n_edges += tx.execute(
+            "INSERT OR IGNORE INTO dependency VALUES (?1, ?2, ?3, ?4)",
+            (kernel_id, &name, referenced.name().unwrap().to_string(), relation),
+        )?;
     }
-
-    println!(
-        "{:>12} {} symbol values [{}] in {:.2?}",
-        "Indexed".green(),
-        n_indexed_symbols,
-        name,
-        time_start.elapsed()
-    );
-    Ok(())
+    Ok(n_edges)
 }
@@ -1,20 +1,31 @@
-use autokernel::bridge::satisfier::SolverConfig;
-use autokernel::bridge::{print_satisfy_result, SymbolValue, Tristate};
+use autokernel::bridge::satisfier::{SatisfyPlan, SolverConfig};
+use autokernel::bridge::types::{SymbolFlags, SymbolType};
+use autokernel::bridge::{diff_symbol_values, print_satisfy_result, Symbol, SymbolValue, Tristate, ValueChange};
 use autokernel::config::Config;
+#[cfg(feature = "index")]
+use autokernel::index;
 use autokernel::script;
+use autokernel::script::KConfig;
 use autokernel::{
-    bridge::{validate_transactions, Bridge},
+    bridge::{audit_written_config, validate_transactions, Bridge, BridgeOptions, Snapshot, ValidationError},
     config,
 };
 use itertools::Itertools;
 
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::fd::AsRawFd;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::time::Instant;
 
-use anyhow::{anyhow, ensure, Context, Ok, Result};
+use anyhow::{anyhow, bail, ensure, Context, Ok, Result};
 use clap::Parser;
 use colored::Colorize;
+use libc::c_int;
+use serde::Serialize;
 use tempfile::tempdir;
 
 /// Autokernel is a tool for managing your kernel configuration that guarantees semantic correctness.
@@ -33,6 +44,48 @@ struct Args {
     /// The bash executable to use for generated scripts, used in shebangs.
     #[clap(short, long, value_name = "BASH", default_value = "/usr/bin/env bash")]
     bash: String,
+    /// A separate, writable directory for all build outputs, which is passed to make as O=.
+    /// Use this if the kernel directory is read-only (e.g. an immutable source tree).
+    #[clap(long, value_name = "DIR", value_hint = clap::ValueHint::DirPath)]
+    build_dir: Option<PathBuf>,
+    /// Extra flags that are passed to the compiler when building the bridge (autokernel_bridge.c),
+    /// for example to add compatibility defines for unusual kernels or to disable warnings.
+    /// The bridge is rebuilt automatically whenever these flags change.
+    #[clap(long, value_name = "CFLAGS", allow_hyphen_values = true)]
+    bridge_cflags: Option<String>,
+    /// Allow the config file and the script to be given as http(s) URLs. They are downloaded
+    /// to a temporary directory before use. Only use this with sources you trust, as scripts can run arbitrary code.
+    #[clap(long)]
+    allow_remote: bool,
+    /// The temporary directory for downloaded remote files
+    #[clap(skip)]
+    remote_dir: Option<PathBuf>,
+    /// The number of warnings that were reported independently of a bridge
+    #[clap(skip)]
+    n_warnings: Cell<usize>,
+    /// Only apply assignments to the given symbol (without CONFIG_ prefix). Can be given multiple times.
+    /// All other assignments of the script are skipped. Unchecked kconfig files are not affected.
+    #[clap(long = "allow", value_name = "SYMBOL")]
+    allowlist: Vec<String>,
+    /// Write all kernel symbols and environment variables that were read by the script to the given file,
+    /// one per line as "symbol NAME" or "env NAME". Useful for build systems to know when to re-run autokernel.
+    #[clap(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    emit_read_set: Option<PathBuf>,
+    /// Print additional information, such as how string values were interpreted for each symbol
+    #[clap(short, long)]
+    verbose: bool,
+    /// Stop applying the config at the first failed symbol assignment and only report that error.
+    /// By default, all assignments are applied and all errors are reported at the end.
+    #[clap(long)]
+    fail_fast: bool,
+    /// Suppress all regular output and only print a single summary line at the end, which
+    /// includes the number of errors and warnings. Intended for CI logs.
+    #[clap(long, conflicts_with = "verbose")]
+    summary_only: bool,
+    /// When an assignment fails, also show the Kconfig definition of the affected symbol,
+    /// including its upstream dependencies.
+    #[clap(long)]
+    show_kconfig_source: bool,
 
     #[clap(subcommand)]
     action: Action,
@@ -46,13 +99,41 @@ struct ActionBuild {
     /// Install the resulting artifacts after building
     #[clap(short, long)]
     install: bool,
+    /// Show a progress bar with an estimated time of arrival instead of the raw make output.
+    /// This requires make to print progress lines like [123/456], otherwise the raw output is shown.
+    #[clap(long)]
+    progress: bool,
+    /// After installing, read back the installed config and verify that it matches the built config
+    #[clap(long, requires = "install")]
+    verify: bool,
 }
 
 #[derive(Debug, clap::Args)]
 struct ActionGenerateConfig {
-    /// The output file, defaults to {kernel_dir}/.config if not given.
+    /// The output file, defaults to {kernel_dir}/.config (or {build_dir}/.config) if not given.
     #[clap(short, long, value_parser, value_name = "DIR", value_hint = clap::ValueHint::FilePath)]
     output: Option<PathBuf>,
+    /// Additionally write a minimal defconfig to the given file, using the kernel's own
+    /// savedefconfig logic. Only symbols that differ from their default value will be included.
+    #[clap(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    savedefconfig: Option<PathBuf>,
+    /// After writing, report all symbols that were assigned by the script
+    /// but do not appear in the written config, together with the reason.
+    #[clap(long)]
+    audit: bool,
+    /// Apply the script a second time from the same baseline and fail if any symbol ends up
+    /// with a different value, which indicates order- or environment-dependent scripting.
+    #[clap(long)]
+    check_deterministic: bool,
+    /// After applying the script, print all symbols whose value differs from the kernel's
+    /// own default value, formatted as a diff. This shows what the script accomplished.
+    #[clap(long)]
+    report_changes: bool,
+    /// Prepend a comment block to the written config, which records the autokernel version,
+    /// kernel version, the applied script and when it was generated. SOURCE_DATE_EPOCH
+    /// is respected for the timestamp.
+    #[clap(long)]
+    stamp: bool,
 }
 
 #[derive(Debug, clap::Args)]
@@ -68,6 +149,17 @@ struct ActionSatisfy {
     /// Recursively satisfy dependencies of encountered symbols
     #[clap(short, long)]
     recursive: bool,
+    /// Also try to make symbols implied by the given symbol visible, so they can take on
+    /// their implied default. Unsatisfiable or conflicting implies are silently skipped.
+    #[clap(long)]
+    implies: bool,
+    /// Show for which symbol each assignment is required, instead of just listing the assignments
+    #[clap(long)]
+    plan: bool,
+    /// If solving fails, print the solver's intermediate state (visited symbols, dependency graph,
+    /// assignments and ambiguities) to help understand where it got stuck
+    #[clap(long)]
+    debug_solver: bool,
 }
 
 #[derive(Debug, clap::Args)]
@@ -80,6 +172,83 @@ struct ActionInfo {
     ignore_config: bool,
 }
 
+#[derive(Debug, clap::Args)]
+struct ActionImpact {
+    /// The assignment to analyze, given as SYMBOL=VALUE
+    assignment: String,
+    /// Don't apply the config before analyzing, instead start with all symbols set to their default values
+    #[clap(short, long)]
+    ignore_config: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct ActionList {
+    /// Also list symbols that were skipped because their type is unknown
+    #[clap(long)]
+    include_unknown: bool,
+    /// Don't apply the config before listing, instead show the default values of all symbols
+    #[clap(short, long)]
+    ignore_config: bool,
+    /// Print the symbols as json, including the members and selection of all choices
+    #[clap(long)]
+    json: bool,
+    /// Only list symbols that have the given kconfig flag set (e.g. CHOICEVAL or NOWRITE)
+    #[clap(long, value_name = "FLAG", value_parser = parse_symbol_flag)]
+    flag: Option<SymbolFlags>,
+}
+
+fn parse_symbol_flag(name: &str) -> Result<SymbolFlags, String> {
+    SymbolFlags::from_name(&name.to_uppercase()).ok_or_else(|| {
+        format!(
+            "unknown flag, expected one of: {}",
+            SymbolFlags::all().iter_names().map(|(name, _)| name).join(", ")
+        )
+    })
+}
+
+#[derive(Debug, clap::Args)]
+struct ActionDiff {
+    /// A kernel image (vmlinux or gzip compressed vmlinuz) built with CONFIG_IKCONFIG,
+    /// whose embedded config should be compared to your configuration
+    #[clap(long, value_name = "IMAGE", value_hint = clap::ValueHint::FilePath)]
+    from_image: PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct ActionCheck {
+    /// An architecture to check, as given to make in ARCH= (e.g. x86_64 or arm64).
+    /// Can be given multiple times.
+    #[clap(long = "arch", value_name = "ARCH", required = true)]
+    arches: Vec<String>,
+    /// Record the final config of each architecture in the index database (see autokernel-index)
+    /// as BUILD-ARCH, and show all symbols whose value differs between the architectures.
+    /// The kernel must already be indexed. Existing configs with the same name are replaced.
+    #[cfg(feature = "index")]
+    #[clap(long, value_name = "BUILD")]
+    index_as: Option<String>,
+    /// The index database to record the configs in
+    #[cfg(feature = "index")]
+    #[clap(long, value_name = "SQLITE_DB", value_hint = clap::ValueHint::FilePath, default_value = "index.db", requires = "index_as")]
+    index_db: PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct ActionExportSnapshot {
+    /// The file to write the snapshot to
+    #[clap(value_hint = clap::ValueHint::FilePath)]
+    output: PathBuf,
+    /// Don't apply the config before exporting, instead export the default values of all symbols
+    #[clap(short, long)]
+    ignore_config: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct ActionFmt {
+    /// The kconfig file to format in-place
+    #[clap(value_hint = clap::ValueHint::FilePath)]
+    file: PathBuf,
+}
+
 #[derive(Debug, clap::Subcommand)]
 enum Action {
     /// First generate a .config file by applying the autokernel config and afterwards build the
@@ -93,35 +262,383 @@ enum Action {
     Satisfy(ActionSatisfy),
     /// Show information about a symbol. Mainly useful to see a symbol's dependencies and dependees.
     Info(ActionInfo),
+    /// Show all symbols that would change their value if the given assignment was made.
+    /// This is useful to understand why enabling one symbol may implicitly change many others.
+    Impact(ActionImpact),
+    /// List all symbols together with their type and current value
+    List(ActionList),
+    /// Run a few sanity checks to verify that the bridge correctly interprets the kernel's
+    /// internal structures. Useful to diagnose problems with new or unusual kernel versions.
+    VerifyBridge,
+    /// Show the number of symbols of each type
+    Stats,
+    /// Show all symbols where the configuration differs from the kernel's defconfig
+    DiffDefconfig,
+    /// Show all symbols where the configuration differs from the config embedded in a kernel image
+    Diff(ActionDiff),
+    /// Save all symbols together with their type, value and dependencies to a file, which
+    /// can be queried later without building the bridge again.
+    ExportSnapshot(ActionExportSnapshot),
+    /// Apply and validate the config for each of the given architectures, using a separate bridge
+    /// and the [arch.NAME] config section (if any) for each of them. Shows which architectures failed.
+    Check(ActionCheck),
+    /// Rewrite a kconfig file in canonical form: sorted, one assignment per line, with
+    /// consistent CONFIG_ prefixes and without comments (except for "is not set" lines).
+    Fmt(ActionFmt),
 }
 
 fn main() {
-    if let Err(err) = try_main() {
-        eprintln!("{}: {}", "error".red(), err);
-        err.chain()
-            .skip(1)
-            .for_each(|cause| eprintln!("{}: {}", "because".yellow(), cause));
+    let mut args = Args::parse();
+    if let Err(err) = try_main(&mut args) {
+        // With --summary-only, the error is already part of the summary line
+        if !args.summary_only {
+            eprintln!("{}: {}", "error".red(), err);
+            err.chain()
+                .skip(1)
+                .for_each(|cause| eprintln!("{}: {}", "because".yellow(), cause));
+        }
         std::process::exit(1);
     }
 }
 
-fn try_main() -> Result<()> {
-    let args = Args::parse();
-    let bridge = Bridge::new(args.kernel_dir.clone(), Some(&args.bash))?;
+fn try_main(args: &mut Args) -> Result<()> {
+    let suppressed = if args.summary_only {
+        match SuppressedOutput::start() {
+            Result::Ok(suppressed) => Some(suppressed),
+            Err(err) => {
+                args.n_warnings.set(args.n_warnings.get() + 1);
+                eprintln!("{}: {:#}, showing all output", "warning".yellow().bold(), err);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut bridge = None;
+    let result = run(args, &mut bridge);
+    if let Some(suppressed) = suppressed {
+        suppressed.finish()?;
+    }
+    if args.summary_only {
+        print_summary_line(args, bridge.as_ref(), &result);
+    }
+    result
+}
+
+fn run(args: &mut Args, bridge: &mut Option<Bridge>) -> Result<()> {
+    let remote_dir = tempdir()?;
+    args.remote_dir = Some(remote_dir.path().to_path_buf());
+    if let Some(url) = args.config.to_str().filter(|c| is_remote(c)).map(str::to_string) {
+        args.config = fetch_remote(args, &url)?;
+    }
+    let args = &*args;
+
+    // Formatting doesn't require the bridge
+    if let Action::Fmt(action) = &args.action {
+        return format_kconfig(action);
+    }
+    // Each architecture requires its own bridge
+    if let Action::Check(action) = &args.action {
+        return check_arches(args, action);
+    }
+    let bridge = bridge.insert(create_bridge(args, None)?);
+
+    let result = match &args.action {
+        Action::Build(action) => build_kernel(args, bridge, action),
+        Action::GenerateConfig(action) => generate_config(args, bridge, action),
+        Action::Satisfy(action) => satisfy_symbol(args, bridge, action),
+        Action::Info(action) => info_symbol(args, bridge, action),
+        Action::Impact(action) => impact_symbol(args, bridge, action),
+        Action::List(action) => list_symbols(args, bridge, action),
+        Action::VerifyBridge => verify_bridge(bridge),
+        Action::DiffDefconfig => diff_defconfig(args, bridge),
+        Action::Diff(action) => diff_image(args, bridge, action),
+        Action::Stats => print_stats(bridge),
+        Action::ExportSnapshot(action) => export_snapshot(args, bridge, action),
+        Action::Fmt(_) | Action::Check(_) => unreachable!(),
+    };
+
+    if let Some(path) = &args.emit_read_set {
+        write_read_set(bridge, path)?;
+    }
+    result
+}
+
+/// Redirects stdout and stderr (including those of child processes) to /dev/null
+/// while active, so that only a final summary line is shown
+struct SuppressedOutput {
+    stdout: c_int,
+    stderr: c_int,
+}
+
+impl SuppressedOutput {
+    fn start() -> Result<Self> {
+        io::stdout().flush()?;
+        io::stderr().flush()?;
+        let null = fs::OpenOptions::new()
+            .write(true)
+            .open("/dev/null")
+            .context("Could not open /dev/null to suppress output")?;
+        let (stdout, stderr) = unsafe { (libc::dup(1), libc::dup(2)) };
+        if stdout < 0 || stderr < 0 {
+            let err = io::Error::last_os_error();
+            unsafe {
+                libc::close(stdout);
+                libc::close(stderr);
+            }
+            return Err(err).context("Could not duplicate stdout and stderr");
+        }
+
+        let suppressed = SuppressedOutput { stdout, stderr };
+        if unsafe { libc::dup2(null.as_raw_fd(), 1) < 0 || libc::dup2(null.as_raw_fd(), 2) < 0 } {
+            let err = io::Error::last_os_error();
+            // Restore whatever was already redirected
+            suppressed.finish()?;
+            return Err(err).context("Could not redirect stdout and stderr");
+        }
+        Ok(suppressed)
+    }
+
+    /// Restores the original stdout and stderr
+    fn finish(self) -> Result<()> {
+        io::stdout().flush()?;
+        io::stderr().flush()?;
+        let restored = unsafe { libc::dup2(self.stdout, 1) >= 0 && libc::dup2(self.stderr, 2) >= 0 };
+        let err = io::Error::last_os_error();
+        unsafe {
+            libc::close(self.stdout);
+            libc::close(self.stderr);
+        }
+        if !restored {
+            return Err(err).context("Could not restore stdout and stderr");
+        }
+        Ok(())
+    }
+}
+
+/// Prints a single line that summarizes the run. Errors are the failed assignments, unmet
+/// requirements and any other error that aborted the run, which is also included in the line.
+/// Warnings include all warnings reported by the bridge, and those reported before it existed.
+fn print_summary_line(args: &Args, bridge: Option<&Bridge>, result: &Result<()>) {
+    let unknown = || "unknown".to_string();
+    let version = bridge.and_then(|b| b.get_env("KERNELVERSION")).unwrap_or_else(unknown);
+    let arch = bridge
+        .and_then(|b| b.arch.clone().or_else(|| b.get_env("ARCH")))
+        .unwrap_or_else(unknown);
+    let (n_set, n_failed, n_bridge_warnings) = bridge.map_or((0, 0, 0), |b| {
+        let history = b.history.borrow();
+        let n_set = history
+            .iter()
+            .filter(|t| t.error.is_none())
+            .map(|t| &t.symbol)
+            .unique()
+            .count();
+        let n_failed = history.iter().filter(|t| t.error.is_some()).count();
+        (n_set, n_failed, b.n_warnings.get())
+    });
+    let n_warnings = n_bridge_warnings + args.n_warnings.get();
+
+    let mut n_errors = n_failed;
+    let mut failure = String::new();
+    if let Err(err) = result {
+        if let Some(UnmetRequirements(n_unmet)) = err.chain().find_map(|e| e.downcast_ref()) {
+            n_errors += n_unmet;
+        } else if !err.chain().any(|e| e.is::<ValidationError>()) {
+            // The failed assignments are already counted
+            n_errors += 1;
+        }
+        failure = format!(", failed: {:#}", err);
+    }
+    println!(
+        "autokernel: applied {} to kernel {} [{}], {} symbols set, {} errors, {} warnings{}",
+        args.config.display(),
+        version,
+        arch,
+        n_set,
+        n_errors,
+        n_warnings,
+        failure
+    );
+}
+
+fn create_bridge(args: &Args, arch: Option<&str>) -> Result<Bridge> {
+    let mut bridge = Bridge::with_options(
+        args.kernel_dir.clone(),
+        BridgeOptions {
+            bash: Some(args.bash.clone()),
+            build_dir: args.build_dir.clone(),
+            bridge_cflags: args.bridge_cflags.clone(),
+            arch: arch.map(str::to_string),
+        },
+    )?;
+    bridge.fail_fast = args.fail_fast;
+    bridge.verbose = args.verbose;
+    bridge.show_kconfig_source = args.show_kconfig_source;
+    if !args.allowlist.is_empty() {
+        bridge.allowlist = Some(
+            args.allowlist
+                .iter()
+                .map(|s| s.trim_start_matches("CONFIG_").to_string())
+                .collect(),
+        );
+    }
+    Ok(bridge)
+}
+
+fn write_read_set(bridge: &Bridge, path: &Path) -> Result<()> {
+    let read_set = bridge.read_set.borrow();
+    let mut out = String::new();
+    for symbol in &read_set.symbols {
+        out.push_str(&format!("symbol {}\n", symbol));
+    }
+    for env in &read_set.env {
+        out.push_str(&format!("env {}\n", env));
+    }
+    fs::write(path, out).context(format!("Could not write read set to {}", path.display()))?;
+    println!("{:>12} read set ({})", "Writing".green(), path.display());
+    Ok(())
+}
+
+fn is_remote(path: &str) -> bool {
+    path.starts_with("https://") || path.starts_with("http://")
+}
+
+/// Downloads the given URL into the remote directory. The file name is kept,
+/// so that the script type can still be detected by its extension.
+fn fetch_remote(args: &Args, url: &str) -> Result<PathBuf> {
+    ensure!(
+        args.allow_remote,
+        "Refusing to load remote file {} (pass --allow-remote to allow this)",
+        url
+    );
+    args.n_warnings.set(args.n_warnings.get() + 1);
+    eprintln!(
+        "{}: loading remote file {}, make sure you trust its source as scripts can execute arbitrary code",
+        "warning".yellow().bold(),
+        url
+    );
+
+    let name = url
+        .split(['?', '#'])
+        .next()
+        .and_then(|u| u.rsplit('/').next())
+        .filter(|n| !n.is_empty())
+        .unwrap_or("remote");
+    let out = args.remote_dir.as_ref().context("Missing remote directory")?.join(name);
+    println!("{:>12} {}", "Downloading".green(), url);
+    let status = Command::new("curl")
+        .args(["--fail", "--silent", "--show-error", "--location", "--output"])
+        .arg(&out)
+        .arg(url)
+        .status()
+        .context("Could not run curl, which is required to download remote files")?;
+    ensure!(status.success(), "Could not download {} (see curl's error above)", url);
+    Ok(out)
+}
+
+/// Loads the config file, and downloads the referenced script if it is a URL
+fn load_config(args: &Args) -> Result<Config> {
+    fetch_remote_script(args, config::load(&args.config)?)
+}
+
+/// Like [`load_config`], but uses the [arch.NAME] section of the given architecture if there is one
+fn load_arch_config(args: &Args, arch: &str) -> Result<Config> {
+    fetch_remote_script(args, config::load(&args.config)?.for_arch(arch))
+}
+
+fn fetch_remote_script(args: &Args, mut config: Config) -> Result<Config> {
+    if is_remote(&config.config.script) {
+        let script = fetch_remote(args, &config.config.script)?;
+        config.config.script = script.to_str().context("Invalid script path")?.to_string();
+    }
+    Ok(config)
+}
+
+/// Applies the script of the given config and validates the resulting transactions.
+/// Validation also happens if the script was aborted early (e.g. due to --fail-fast),
+/// so that the diagnostics of all recorded transactions are shown.
+fn apply_script(config: &Config, bridge: &Bridge) -> Result<()> {
+    let result = script::apply(&config.config.script, bridge);
+    validate_transactions(&bridge.history.borrow(), bridge)?;
+    result
+}
+
+/// Returned by [`check_requirements`] with the number of unmet requirements
+#[derive(thiserror::Error, Debug)]
+#[error("{0} of the required symbol values are not met")]
+struct UnmetRequirements(usize);
+
+/// Ensures that all requirements from `config.require` and `config.require_any`
+/// hold in the current configuration
+fn check_requirements(config: &Config, bridge: &Bridge) -> Result<()> {
+    let mut n_unmet = 0;
+    for requirement in &config.config.require {
+        let (name, value) = requirement
+            .split_once('=')
+            .with_context(|| format!("Invalid requirement '{}', expected SYMBOL=value", requirement))?;
+        let name = name.trim().trim_start_matches("CONFIG_");
+        let symbol = bridge
+            .try_symbol(name)
+            .with_context(|| format!("Invalid requirement '{}'", requirement))?;
+        let expected = symbol
+            .coerce_auto_value(value.trim().trim_matches('"'))
+            .with_context(|| format!("Invalid requirement '{}'", requirement))?;
+        let actual = symbol.get_value()?;
+        if actual != expected {
+            n_unmet += 1;
+            eprintln!(
+                "{}: required {}={} but the final value is {}",
+                "error".red().bold(),
+                name.blue(),
+                expected,
+                actual
+            );
+        }
+    }
+
+    for group in &config.config.require_any {
+        let mut values = Vec::new();
+        for name in group {
+            let name = name.trim_start_matches("CONFIG_");
+            let symbol = bridge
+                .try_symbol(name)
+                .with_context(|| format!("Invalid require_any group {:?}", group))?;
+            ensure!(
+                matches!(symbol.symbol_type(), SymbolType::Boolean | SymbolType::Tristate),
+                "Invalid require_any group {:?}: {} is not a boolean or tristate symbol",
+                group,
+                name
+            );
+            values.push((name, symbol.get_tristate_value()));
+        }
+        if values.iter().all(|(_, value)| *value == Tristate::No) {
+            n_unmet += 1;
+            eprintln!(
+                "{}: at least one of these symbols must be enabled: {}",
+                "error".red().bold(),
+                values
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name.blue(), value.to_string().color(value.color())))
+                    .join(", ")
+            );
+        }
+    }
 
-    match &args.action {
-        Action::Build(action) => build_kernel(&args, &bridge, action),
-        Action::GenerateConfig(action) => generate_config(&args, &bridge, action),
-        Action::Satisfy(action) => satisfy_symbol(&args, &bridge, action),
-        Action::Info(action) => info_symbol(&args, &bridge, action),
+    if n_unmet > 0 {
+        return Err(UnmetRequirements(n_unmet).into());
     }
+    let n_requirements = config.config.require.len() + config.config.require_any.len();
+    if n_requirements > 0 {
+        println!("{:>12} {} required symbol values", "Verified".green(), n_requirements);
+    }
+    Ok(())
 }
 
 fn satisfy_symbol(args: &Args, bridge: &Bridge, action: &ActionSatisfy) -> Result<()> {
     if !action.ignore_config {
-        let config = config::load(&args.config)?;
-        script::apply(config.config.script, bridge)?;
-        validate_transactions(&bridge.history.borrow())?;
+        let config = load_config(args)?;
+        apply_script(&config, bridge)?;
     }
 
     let value: Tristate = action
@@ -133,30 +650,44 @@ fn satisfy_symbol(args: &Args, bridge: &Bridge, action: &ActionSatisfy) -> Resul
         action.symbol.blue(),
         value.to_string().color(value.color())
     );
-    let satisfying_configuration = bridge
-        .symbol(&action.symbol)
-        .context("This symbol doesn't exist")?
-        .satisfy(SolverConfig {
-            recursive: action.recursive,
-            desired_value: value,
-            ..SolverConfig::default()
-        });
+    let plan = bridge.try_symbol(&action.symbol)?.satisfy_plan(SolverConfig {
+        recursive: action.recursive,
+        implies: action.implies,
+        debug: action.debug_solver,
+        desired_value: value,
+        ..SolverConfig::default()
+    });
 
-    match satisfying_configuration {
-        Result::Ok(c) if c.is_empty() => println!("Nothing to do :)"),
-        _ => print_satisfy_result(&satisfying_configuration),
+    match plan {
+        Result::Ok(plan) if plan.is_empty() => println!("Nothing to do :)"),
+        Result::Ok(plan) if action.plan => print_satisfy_plan(&plan),
+        _ => print_satisfy_result(&plan.map(|p| p.assignments())),
     };
     Ok(())
 }
 
+fn print_satisfy_plan(plan: &SatisfyPlan) {
+    for step in &plan.steps {
+        println!(
+            "  {} {} {}",
+            step.symbol.blue(),
+            format!("\"{}\"", step.value).color(step.value.color()),
+            if step.direct {
+                format!("(required by {})", step.required_for).dimmed()
+            } else {
+                format!("(prerequisite of {})", step.required_for).dimmed()
+            }
+        );
+    }
+}
+
 fn info_symbol(args: &Args, bridge: &Bridge, action: &ActionInfo) -> Result<()> {
     if !action.ignore_config {
-        let config = config::load(&args.config)?;
-        script::apply(config.config.script, bridge)?;
-        validate_transactions(&bridge.history.borrow())?;
+        let config = load_config(args)?;
+        apply_script(&config, bridge)?;
     }
 
-    let symbol = bridge.symbol(&action.symbol).context("This symbol doesn't exist")?;
+    let symbol = bridge.try_symbol(&action.symbol)?;
     println!("Information for {}:", symbol.name().unwrap().blue());
     println!("  Current value:        {:?}", symbol.get_value()?);
     println!("  Flags:                {:?}", symbol.flags());
@@ -205,41 +736,666 @@ fn info_symbol(args: &Args, bridge: &Bridge, action: &ActionInfo) -> Result<()>
         Err(e) => println!("  Reverse dependencies could not be parsed: {}", e),
     }
 
+    match symbol.implied_expression() {
+        Result::Ok(expr) => {
+            println!(
+                "  {}",
+                "// Symbols that imply this symbol only change its default value, if it is visible.".dimmed()
+            );
+            println!(
+                "  {}",
+                "// They never prevent it from being set to a lower value.".dimmed()
+            );
+            println!("  Implied by:           {}", expr.display(bridge));
+            println!(
+                "    => implied default: {}",
+                expr.eval().map_or("could not evaluate".to_string().red(), |v| v
+                    .to_string()
+                    .color(v.color()))
+            );
+        }
+        Err(e) => println!("  Implied expression could not be parsed: {}", e),
+    }
+
+    Ok(())
+}
+
+fn list_symbols(args: &Args, bridge: &Bridge, action: &ActionList) -> Result<()> {
+    if !action.ignore_config {
+        let config = load_config(args)?;
+        apply_script(&config, bridge)?;
+    }
+
+    if action.json {
+        return list_symbols_json(bridge, action);
+    }
+
+    let symbols = match action.flag {
+        Some(flag) => bridge
+            .symbols_with_flag(flag)
+            .into_iter()
+            .filter(|s| s.name().is_some() && s.symbol_type() != SymbolType::Unknown)
+            .collect_vec(),
+        None => bridge
+            .name_to_symbol
+            .values()
+            .map(|s| bridge.wrap_symbol(*s))
+            .filter(|s| !s.is_const())
+            .collect_vec(),
+    };
+    for symbol in symbols.iter().sorted_by_key(|s| s.name_owned()) {
+        println!(
+            "{} {:?} {:?}",
+            symbol.name().unwrap().blue(),
+            symbol.symbol_type(),
+            symbol.get_string_value()
+        );
+    }
+
+    if action.include_unknown {
+        // These symbols were skipped when loading the bridge. Show some of their
+        // properties to help figuring out whether they should be usable.
+        for symbol in bridge
+            .unknown_symbols()
+            .iter()
+            .filter(|s| action.flag.is_none_or(|flag| s.flags().intersects(flag)))
+            .sorted_by_key(|s| s.name_owned())
+        {
+            println!(
+                "{} {} {:?} {}",
+                symbol.name().unwrap().yellow(),
+                "Unknown".dimmed(),
+                symbol.get_string_value(),
+                format!("[prompts={}, flags={:?}]", symbol.prompt_count(), symbol.flags()).dimmed()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct JsonSymbol {
+    name: String,
+    #[serde(rename = "type")]
+    symbol_type: SymbolType,
+    value: String,
+    /// The id of the choice this symbol is a member of
+    #[serde(skip_serializing_if = "Option::is_none")]
+    choice_group: Option<String>,
+}
+
+#[derive(Serialize)]
+struct JsonChoice {
+    id: String,
+    members: Vec<String>,
+    selected: Option<String>,
+}
+
+#[derive(Serialize)]
+struct JsonList {
+    symbols: Vec<JsonSymbol>,
+    choices: Vec<JsonChoice>,
+}
+
+fn list_symbols_json(bridge: &Bridge, action: &ActionList) -> Result<()> {
+    // Choices usually have no name, so they are identified by their position instead
+    let mut choices = Vec::new();
+    let mut choice_groups = HashMap::new();
+    for symbol in bridge
+        .symbols
+        .iter()
+        .map(|s| bridge.wrap_symbol(*s))
+        .filter(|s| s.is_choice())
+    {
+        let id = symbol
+            .name_owned()
+            .unwrap_or_else(|| format!("choice{}", choices.len()));
+        let members = symbol
+            .choices()?
+            .into_iter()
+            .filter_map(|s| bridge.wrap_symbol(s).name_owned())
+            .collect_vec();
+        for member in &members {
+            choice_groups.insert(member.clone(), id.clone());
+        }
+        choices.push(JsonChoice {
+            id,
+            members,
+            selected: symbol.selected_choice()?.and_then(|s| s.name_owned()),
+        });
+    }
+
+    let mut symbols = bridge
+        .name_to_symbol
+        .values()
+        .map(|s| bridge.wrap_symbol(*s))
+        .filter(|s| !s.is_const())
+        .collect_vec();
+    if action.include_unknown {
+        symbols.extend(bridge.unknown_symbols());
+    }
+    let symbols = symbols
+        .into_iter()
+        .filter(|s| action.flag.is_none_or(|flag| s.flags().intersects(flag)))
+        .filter_map(|s| {
+            let name = s.name_owned()?;
+            Some(JsonSymbol {
+                choice_group: choice_groups.get(&name).cloned(),
+                name,
+                symbol_type: s.symbol_type(),
+                value: s.get_string_value(),
+            })
+        })
+        .sorted_by(|a, b| a.name.cmp(&b.name))
+        .collect_vec();
+
+    println!("{}", serde_json::to_string_pretty(&JsonList { symbols, choices })?);
+    Ok(())
+}
+
+fn print_stats(bridge: &Bridge) -> Result<()> {
+    let counts = bridge.symbol_count_by_type();
+    println!(
+        "Symbols in kernel {} [{} total]:",
+        bridge.get_env("KERNELVERSION").unwrap_or_default().blue(),
+        counts.values().sum::<usize>()
+    );
+    for (symbol_type, count) in counts {
+        println!("  {:<10} {:>6}", format!("{}:", symbol_type.as_ref()), count);
+    }
+    Ok(())
+}
+
+fn export_snapshot(args: &Args, bridge: &Bridge, action: &ActionExportSnapshot) -> Result<()> {
+    if !action.ignore_config {
+        let config = load_config(args)?;
+        apply_script(&config, bridge)?;
+    }
+
+    bridge.export_snapshot(&action.output)?;
+    println!("{:>12} snapshot ({})", "Exported".green(), action.output.display());
+    Ok(())
+}
+
+fn diff_defconfig(args: &Args, bridge: &Bridge) -> Result<()> {
+    let config = load_config(args)?;
+    apply_script(&config, bridge)?;
+    let values = bridge.symbol_values();
+
+    let tmpdir = tempdir()?;
+    let defconfig = tmpdir.path().join("defconfig");
+    println!("{:>12} defconfig", "Generating".green());
+    ensure!(
+        bridge
+            .make_command()
+            .arg(format!("KCONFIG_CONFIG={}", defconfig.display()))
+            .arg("defconfig")
+            .stdout(Stdio::null())
+            .status()
+            .context("Failed to generate defconfig")?
+            .success(),
+        "Failed to generate defconfig"
+    );
+
+    let defconfig_values = values_of_config(bridge, &defconfig)?;
+    print_config_diff(&defconfig_values, &values, "the defconfig", "defconfig");
+    Ok(())
+}
+
+fn diff_image(args: &Args, bridge: &Bridge, action: &ActionDiff) -> Result<()> {
+    let config = load_config(args)?;
+    apply_script(&config, bridge)?;
+    let values = bridge.symbol_values();
+
+    println!(
+        "{:>12} embedded config ({})",
+        "Extracting".green(),
+        action.from_image.display()
+    );
+    // The image config is loaded with conf_read like a regular .config instead of being
+    // applied as a script, so assignments to unknown or unmet symbols behave as in kbuild.
+    let tmpdir = tempdir()?;
+    let image_config = tmpdir.path().join("image.config");
+    fs::write(&image_config, script::extract_ikconfig(&action.from_image)?)?;
+
+    let image_values = values_of_config(bridge, &image_config)?;
+    print_config_diff(&image_values, &values, "the image's config", "image");
+    Ok(())
+}
+
+/// Returns the symbol values that result from loading the given .config file,
+/// without changing the current state of the bridge.
+fn values_of_config(bridge: &Bridge, path: &Path) -> Result<BTreeMap<String, SymbolValue>> {
+    let snapshot = bridge.snapshot()?;
+    let ret = bridge.read_config_unchecked(path);
+    let values = bridge.symbol_values();
+    bridge.restore(&snapshot)?;
+    ret?;
+    Ok(values)
+}
+
+fn print_config_diff(
+    reference: &BTreeMap<String, SymbolValue>,
+    values: &BTreeMap<String, SymbolValue>,
+    reference_name: &str,
+    short_name: &str,
+) {
+    let changes = diff_symbol_values(reference, values);
+    if changes.is_empty() {
+        println!("Your configuration is identical to {}", reference_name);
+    } else {
+        println!(
+            "{} symbols differ from {} ({} -> yours):",
+            changes.len(),
+            reference_name,
+            short_name
+        );
+        print_value_changes(&changes);
+    }
+}
+
+fn format_kconfig(action: &ActionFmt) -> Result<()> {
+    let kconfig = KConfig::new(&action.file)?;
+    fs::write(&action.file, kconfig.format()).context(format!("Could not write {}", action.file.display()))?;
+    println!("{:>12} {}", "Formatted".green(), action.file.display());
+    Ok(())
+}
+
+fn check_arches(args: &Args, action: &ActionCheck) -> Result<()> {
+    #[cfg(feature = "index")]
+    let mut recorded = match &action.index_as {
+        Some(build) => {
+            let mut conn = rusqlite::Connection::open(&action.index_db)?;
+            index::create_schema(&mut conn)?;
+            Some((conn, build, Vec::new()))
+        }
+        None => None,
+    };
+
+    let mut results = Vec::new();
+    for arch in &action.arches {
+        println!("{:>12} config for {}", "Checking".cyan(), arch.blue());
+        let result = create_bridge(args, Some(arch)).and_then(|bridge| {
+            let config = load_arch_config(args, arch)?;
+            apply_script(&config, &bridge)?;
+            check_requirements(&config, &bridge)?;
+            #[cfg(feature = "index")]
+            if let Some((conn, build, entries)) = &mut recorded {
+                let name = format!("{}-{}", build, arch);
+                let config_id = index_arch_config(conn, &bridge, &name, arch)?;
+                entries.push((name, config_id));
+            }
+            Ok(())
+        });
+        if let Err(err) = &result {
+            eprintln!("{}: {:#}", "error".red(), err);
+        }
+        results.push((arch, result.is_ok()));
+    }
+
+    println!("Results:");
+    for (arch, passed) in &results {
+        if *passed {
+            println!("{:>12} {}", "Passed".green(), arch);
+        } else {
+            println!("{:>12} {}", "Failed".red(), arch);
+        }
+    }
+
+    // Only the passed architectures were recorded, so they are the entries of the matrix
+    #[cfg(feature = "index")]
+    if let Some((conn, _, entries)) = &recorded {
+        let entries = entries
+            .iter()
+            .map(|(name, config_id)| {
+                Ok(index::MatrixEntry {
+                    label: name.clone(),
+                    values: index::config_values(conn, config_id)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if entries.len() >= 2 {
+            index::print_matrix(&entries)?;
+        } else {
+            println!(
+                "{:>12} matrix report, at least two architectures must pass",
+                "Skipping".yellow()
+            );
+        }
+    }
+
+    let n_failed = results.iter().filter(|(_, passed)| !passed).count();
+    ensure!(n_failed == 0, "{} of {} architectures failed", n_failed, results.len());
+    Ok(())
+}
+
+/// Records the current symbol values of the given bridge in the index as a config
+/// with the given name, replacing any previous config of the same name. Returns its id.
+#[cfg(feature = "index")]
+fn index_arch_config(conn: &mut rusqlite::Connection, bridge: &Bridge, name: &str, arch: &str) -> Result<String> {
+    let kernel_id = index::indexed_kernel_id(conn, bridge)?;
+    let arch = Some(arch.to_string());
+    let tx = conn.transaction()?;
+    index::remove_config(&tx, &kernel_id, name, arch.as_ref())?;
+    let config_id = index::index_values(bridge, &tx, &kernel_id, name, None, arch.as_ref())?;
+    tx.commit()?;
+    Ok(config_id)
+}
+
+fn verify_bridge(bridge: &Bridge) -> Result<()> {
+    let check_symbol_table = || -> Result<()> {
+        let mut n_named = 0;
+        for (i, symbol) in bridge.symbols.iter().enumerate() {
+            ensure!(!symbol.is_null(), "symbol {} is a null pointer", i);
+            let symbol = unsafe { &**symbol };
+            // Read the raw discriminant, as an out of range value must not be interpreted as SymbolType
+            let discriminant = unsafe { std::ptr::addr_of!(symbol.symbol_type).cast::<u32>().read() };
+            ensure!(
+                discriminant <= SymbolType::String as u32,
+                "symbol {} ({:?}) has an invalid type discriminant {}",
+                i,
+                symbol.name(),
+                discriminant
+            );
+            if !symbol.is_const() && !symbol.name.is_null() && symbol.symbol_type != SymbolType::Unknown {
+                n_named += 1;
+            }
+        }
+
+        // Names read from a misinterpreted structure would collide or be missing
+        let n_by_name = bridge
+            .name_to_symbol
+            .values()
+            .filter(|s| !unsafe { &***s }.is_const())
+            .count();
+        ensure!(
+            n_by_name == n_named,
+            "{} named symbols were loaded, but {} can be looked up by name",
+            n_named,
+            n_by_name
+        );
+        Ok(())
+    };
+    let check_constant_symbols = || -> Result<()> {
+        let names = bridge
+            .symbols
+            .get(..3)
+            .context("the symbol table contains less than 3 symbols")?
+            .iter()
+            .map(|s| (!s.is_null()).then(|| bridge.wrap_symbol(*s).name_owned()).flatten())
+            .collect_vec();
+        ensure!(
+            names == [Some("y".to_string()), Some("n".to_string()), Some("m".to_string())],
+            "expected the constant symbols y, n, m but got {:?}",
+            names
+        );
+        Ok(())
+    };
+    let check_modules = || -> Result<()> {
+        let symbol_type = bridge.try_symbol("MODULES")?.symbol_type();
+        ensure!(
+            symbol_type == SymbolType::Boolean,
+            "expected MODULES to be of type Boolean but got {:?}",
+            symbol_type
+        );
+        Ok(())
+    };
+    let check_kernel_version = || -> Result<()> {
+        let version = bridge.get_env("KERNELVERSION").unwrap_or_default();
+        ensure!(!version.is_empty(), "KERNELVERSION is empty");
+        Ok(())
+    };
+    let check_round_trip = || -> Result<()> {
+        let snapshot = bridge.snapshot()?;
+        let mut symbol = bridge.try_symbol("LOCALVERSION")?;
+        let ret = symbol.set_value(SymbolValue::String("-autokernel-verify".to_string()));
+        let value = symbol.get_string_value();
+        bridge.restore(&snapshot)?;
+        ret?;
+        ensure!(
+            value == "-autokernel-verify",
+            "assigned LOCALVERSION but read back {:?}",
+            value
+        );
+        Ok(())
+    };
+
+    let checks: [(&str, &dyn Fn() -> Result<()>); 5] = [
+        ("symbol table", &check_symbol_table),
+        ("constant symbols", &check_constant_symbols),
+        ("MODULES symbol type", &check_modules),
+        ("kernel version", &check_kernel_version),
+        ("string value round-trip", &check_round_trip),
+    ];
+    let mut n_failed = 0;
+    for (name, check) in checks {
+        match check() {
+            Result::Ok(()) => println!("{:>12} {}", "Passed".green(), name),
+            Err(e) => {
+                n_failed += 1;
+                println!("{:>12} {} ({})", "Failed".red(), name, e);
+            }
+        }
+    }
+    ensure!(n_failed == 0, "{} bridge checks failed", n_failed);
+    Ok(())
+}
+
+fn impact_symbol(args: &Args, bridge: &Bridge, action: &ActionImpact) -> Result<()> {
+    if !action.ignore_config {
+        let config = load_config(args)?;
+        apply_script(&config, bridge)?;
+    }
+
+    let (name, value) = action
+        .assignment
+        .split_once('=')
+        .context("The assignment must be given as SYMBOL=VALUE")?;
+    let name = name.trim_start_matches("CONFIG_");
+    let changes = bridge.impact_of(name, SymbolValue::Auto(value.to_string()))?;
+    if changes.is_empty() {
+        println!("Assigning {}={} would not change any symbols", name.blue(), value);
+    } else {
+        println!(
+            "Assigning {}={} would change {} symbols:",
+            name.blue(),
+            value,
+            changes.len()
+        );
+        print_value_changes(&changes);
+    }
+    Ok(())
+}
+
+fn print_value_changes(changes: &[ValueChange]) {
+    for (name, before, after) in changes {
+        println!("  {} {:?} -> {:?}", name.blue(), before, after);
+    }
+}
+
+fn print_value_changes_as_diff(changes: &[ValueChange]) {
+    for (name, before, after) in changes {
+        println!("{}", format!("-CONFIG_{}={}", name, before).red());
+        println!("{}", format!("+CONFIG_{}={}", name, after).green());
+    }
+}
+
+/// Reads back an installed config and ensures that it yields the same
+/// symbol values as the currently loaded configuration.
+fn verify_installed_config(bridge: &Bridge, installed: &str) -> Result<()> {
+    let expected = bridge.symbol_values();
+    let snapshot = bridge.snapshot()?;
+    let ret = bridge.read_config_unchecked(installed);
+    let actual = bridge.symbol_values();
+    bridge.restore(&snapshot)?;
+    ret.context(format!("Could not read back installed config {}", installed))?;
+
+    let changes = diff_symbol_values(&expected, &actual);
+    if !changes.is_empty() {
+        print_value_changes(&changes);
+        bail!(
+            "The installed config {} differs from the built config in {} symbols",
+            installed,
+            changes.len()
+        );
+    }
+    println!("{:>12} installed config {}", "Verified".green(), installed);
     Ok(())
 }
 
 fn generate_config(args: &Args, bridge: &Bridge, action: &ActionGenerateConfig) -> Result<()> {
-    let config = config::load(&args.config)?;
+    let config = load_config(args)?;
+    let baseline = bridge.snapshot()?;
     println!("{:>12} configuration ({})", "Applying".green(), args.config.display());
-    script::apply(config.config.script, bridge)?;
-    validate_transactions(&bridge.history.borrow())?;
+    apply_script(&config, bridge)?;
+    if action.check_deterministic {
+        check_deterministic(&config, bridge, &baseline)?;
+    }
+    check_requirements(&config, bridge)?;
+    if action.report_changes {
+        let defaults = values_of_config(bridge, Path::new("/dev/null"))?;
+        let changes = diff_symbol_values(&defaults, &bridge.symbol_values());
+        println!("{} symbols differ from their default value:", changes.len());
+        print_value_changes_as_diff(&changes);
+    }
 
-    let output = action.output.clone().unwrap_or_else(|| args.kernel_dir.join(".config"));
+    let output = action
+        .output
+        .clone()
+        .unwrap_or_else(|| bridge.output_dir().join(".config"));
     println!("{:>12} kernel config ({})", "Writing".green(), output.display());
-    bridge.write_config(output)?;
+    bridge.write_config(&output)?;
+    if action.stamp {
+        stamp_config(args, &config, bridge, &output)?;
+    }
+
+    if action.audit {
+        let missing = audit_written_config(&bridge.history.borrow(), bridge, &output)?;
+        println!(
+            "{:>12} written config [{} assigned symbols missing]",
+            "Audited".green(),
+            missing.len()
+        );
+    }
+
+    if let Some(defconfig) = &action.savedefconfig {
+        println!("{:>12} defconfig ({})", "Writing".green(), defconfig.display());
+        bridge.write_defconfig(defconfig)?;
+    }
+    Ok(())
+}
+
+/// Prepends a comment block with information about how the given config was generated
+fn stamp_config(args: &Args, config: &Config, bridge: &Bridge, path: &Path) -> Result<()> {
+    let timestamp = match std::env::var("SOURCE_DATE_EPOCH") {
+        std::result::Result::Ok(epoch) => epoch.parse().context("Invalid SOURCE_DATE_EPOCH")?,
+        Err(_) => std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs(),
+    };
+    let header = format!(
+        "#\n# Generated by {} {}\n# Kernel version: {}\n# Config: {}\n# Script: {}\n# Date: {}\n",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        bridge.get_env("KERNELVERSION").unwrap_or_default(),
+        args.config.display(),
+        config.config.script,
+        format_utc(timestamp)
+    );
+    let content = fs::read_to_string(path).context(format!("Could not read {}", path.display()))?;
+    fs::write(path, header + &content).context(format!("Could not write {}", path.display()))
+}
+
+/// Formats seconds since the unix epoch as an ISO 8601 date in UTC
+fn format_utc(timestamp: u64) -> String {
+    let (days, secs) = ((timestamp / 86400) as i64, timestamp % 86400);
+    // Converts days since the epoch to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Applies the script again, starting from the given baseline, and ensures
+/// that all symbols end up with the same values as after the first run.
+fn check_deterministic(config: &Config, bridge: &Bridge, baseline: &Snapshot) -> Result<()> {
+    let expected = bridge.symbol_values();
+    // Keep the history and read set of the first run, it was already validated
+    let history = bridge.history.take();
+    let read_set = bridge.read_set.borrow().clone();
+    bridge.restore(baseline)?;
+    println!("{:>12} configuration to check determinism", "Reapplying".green());
+    let result = script::apply(&config.config.script, bridge);
+    let second_history = bridge.history.replace(history);
+    *bridge.read_set.borrow_mut() = read_set;
+    result.context("Applying the configuration failed on the second run")?;
+
+    // The first run had no failed assignments, otherwise validation would have failed
+    let failed = second_history.iter().filter(|t| t.error.is_some()).collect_vec();
+    if !failed.is_empty() {
+        for t in &failed {
+            eprintln!(
+                "{}: failed to assign symbol {} to {:?} at {}:{} on the second run: {}",
+                "error".red().bold(),
+                t.symbol,
+                t.value,
+                t.file,
+                t.line,
+                t.error.as_ref().unwrap()
+            );
+        }
+        bail!(
+            "The configuration is not deterministic, {} assignments only failed on the second run",
+            failed.len()
+        );
+    }
+
+    let changes = diff_symbol_values(&expected, &bridge.symbol_values());
+    if !changes.is_empty() {
+        print_value_changes(&changes);
+        bail!(
+            "The configuration is not deterministic, {} symbols differ between two runs",
+            changes.len()
+        );
+    }
+    println!("{:>12} configuration is deterministic", "Verified".green());
     Ok(())
 }
 
 fn build_kernel(args: &Args, bridge: &Bridge, action: &ActionBuild) -> Result<()> {
-    let config = config::load(&args.config)?;
+    let config = load_config(args)?;
     unsafe { libc::umask(0o022) };
 
     // Clean output from previous builds if requested
     if action.clean {
         println!("{:>12} `make clean`", "Running".green());
-        ensure!(Command::new("make")
+        ensure!(bridge
+            .make_command()
             .arg("clean")
-            .current_dir(&args.kernel_dir)
             .status()
             .context("Failed to clean")?
             .success());
     }
 
-    script::apply(&config.config.script, bridge)?;
-    validate_transactions(&bridge.history.borrow())?;
+    apply_script(&config, bridge)?;
+    check_requirements(&config, bridge)?;
 
     let tmpdir = tempdir()?;
-    let config_output = args.kernel_dir.join(".config");
+    let config_output = bridge.output_dir().join(".config");
     let initramfs_out = tmpdir.path().join("initramfs.img");
 
     // If an initramfs is built, ensure that the relevant option is enabled
@@ -272,11 +1428,7 @@ fn build_kernel(args: &Args, bridge: &Bridge, action: &ActionBuild) -> Result<()
         );
         bridge.write_config(&config_output)?;
         println!("{:>12} `make` [stage 1/2]", "Running".green());
-        ensure!(Command::new("make")
-            .current_dir(&args.kernel_dir)
-            .status()
-            .context("Failed to make kernel")?
-            .success());
+        run_make(bridge.make_command(), action.progress).context("Failed to make kernel")?;
 
         // Build the initramfs now that the modules are built, and
         // set the INITRAMFS_SOURCE to the output file for the next step
@@ -291,21 +1443,13 @@ fn build_kernel(args: &Args, bridge: &Bridge, action: &ActionBuild) -> Result<()
         );
         bridge.write_config(&config_output)?;
         println!("{:>12} `make` [stage 2/2]", "Running".green());
-        ensure!(Command::new("make")
-            .current_dir(&args.kernel_dir)
-            .status()
-            .context("Failed to make kernel")?
-            .success());
+        run_make(bridge.make_command(), action.progress).context("Failed to make kernel")?;
     } else {
         println!("{:>12} kernel config ({})", "Writing".green(), config_output.display());
         bridge.write_config(&config_output)?;
 
         println!("{:>12} `make`", "Running".green());
-        ensure!(Command::new("make")
-            .current_dir(&args.kernel_dir)
-            .status()
-            .context("Failed to make kernel")?
-            .success());
+        run_make(bridge.make_command(), action.progress).context("Failed to make kernel")?;
 
         if config.initramfs.enable {
             build_initramfs(args, bridge, &config, tmpdir.path(), &initramfs_out)?;
@@ -321,7 +1465,10 @@ fn build_kernel(args: &Args, bridge: &Bridge, action: &ActionBuild) -> Result<()
         if config.config.install.enable {
             let out = replace_variables(&config.config.install.path);
             println!("{:>12} config to {}", "Installing".green(), out);
-            fs::copy(config_output, out)?;
+            fs::copy(&config_output, &out)?;
+            if action.verify {
+                verify_installed_config(bridge, &out)?;
+            }
         }
 
         if config.initramfs.install.enable && !config.initramfs.builtin {
@@ -333,10 +1480,10 @@ fn build_kernel(args: &Args, bridge: &Bridge, action: &ActionBuild) -> Result<()
         if config.modules.install.enable {
             let out = replace_variables(&config.modules.install.path);
             println!("{:>12} modules to {}", "Installing".green(), out);
-            ensure!(Command::new("make")
+            ensure!(bridge
+                .make_command()
                 .arg("modules_install")
                 .arg(format!("INSTALL_MOD_PATH={}", out))
-                .current_dir(&args.kernel_dir)
                 .status()
                 .context("Failed to install modules")?
                 .success());
@@ -344,9 +1491,9 @@ fn build_kernel(args: &Args, bridge: &Bridge, action: &ActionBuild) -> Result<()
 
         if config.kernel.install.enable {
             println!("{:>12} kernel with `make install`", "Installing".green());
-            ensure!(Command::new("make")
+            ensure!(bridge
+                .make_command()
                 .arg("install")
-                .current_dir(&args.kernel_dir)
                 .status()
                 .context("Failed to install kernel")?
                 .success());
@@ -358,20 +1505,164 @@ fn build_kernel(args: &Args, bridge: &Bridge, action: &ActionBuild) -> Result<()
     Ok(())
 }
 
+/// Parses a progress line of the form `[done/total] ...`
+fn parse_progress(line: &str) -> Option<(usize, usize)> {
+    let (progress, _) = line.trim_start().strip_prefix('[')?.split_once(']')?;
+    let (done, total) = progress.split_once('/')?;
+    let (done, total) = (done.trim().parse().ok()?, total.trim().parse().ok()?);
+    (total > 0 && done <= total).then_some((done, total))
+}
+
+/// Runs the given make command. If progress is requested, the output is parsed for
+/// progress lines which are condensed into a single progress bar with an ETA.
+/// All other lines are passed through unchanged.
+fn run_make(mut command: Command, progress: bool) -> Result<()> {
+    if !progress {
+        ensure!(command.status()?.success(), "make exited with an error");
+        return Ok(());
+    }
+
+    let time_start = Instant::now();
+    let mut child = command.stdout(Stdio::piped()).spawn()?;
+    let stdout = child.stdout.take().context("Could not capture make output")?;
+    let mut progress_shown = false;
+    for line in BufReader::new(stdout).lines() {
+        let line = line?;
+        match parse_progress(&line) {
+            Some((done, total)) => {
+                let fraction = done as f64 / total as f64;
+                let width = 30;
+                let filled = (fraction * width as f64) as usize;
+                let eta = match done {
+                    0 => "?".to_string(),
+                    _ => format!(
+                        "{:.0?}",
+                        time_start.elapsed().mul_f64((total - done) as f64 / done as f64)
+                    ),
+                };
+                print!(
+                    "\r{:>12} [{}{}] {:>3}% ({}/{}) ETA {:<10}",
+                    "Building".cyan(),
+                    "=".repeat(filled),
+                    " ".repeat(width - filled),
+                    (fraction * 100.0) as usize,
+                    done,
+                    total,
+                    eta
+                );
+                io::stdout().flush()?;
+                progress_shown = true;
+            }
+            None => {
+                if progress_shown {
+                    println!();
+                    progress_shown = false;
+                }
+                println!("{}", line);
+            }
+        }
+    }
+    if progress_shown {
+        println!();
+    }
+
+    ensure!(child.wait()?.success(), "make exited with an error");
+    Ok(())
+}
+
+/// Returns the names of the modules for all symbols in `initramfs.boot_modules` that are
+/// built as modules. Built-in symbols are skipped, as their code needs no module.
+fn boot_modules(bridge: &Bridge, config: &Config) -> Result<Vec<String>> {
+    let mut modules = Vec::new();
+    for name in &config.initramfs.boot_modules {
+        let name = name.trim_start_matches("CONFIG_");
+        let symbol = bridge
+            .try_symbol(name)
+            .context("Invalid symbol in initramfs.boot_modules")?;
+        match symbol.get_tristate_value() {
+            Tristate::Mod => (),
+            Tristate::Yes => continue,
+            Tristate::No => {
+                bridge.count_warning();
+                eprintln!(
+                    "{}: boot module symbol {} is not enabled",
+                    "warning".yellow().bold(),
+                    name
+                );
+                continue;
+            }
+        }
+
+        let names = module_names(&symbol);
+        if names.is_empty() {
+            bridge.count_warning();
+            eprintln!(
+                "{}: could not determine the module built by {}",
+                "warning".yellow().bold(),
+                name
+            );
+        }
+        modules.extend(names);
+    }
+
+    modules.sort();
+    modules.dedup();
+    if !config.initramfs.boot_modules.is_empty() {
+        println!("{:>12} {} boot modules", "Resolved".green(), modules.len());
+    }
+    Ok(modules)
+}
+
+/// Returns the names of the modules built by the given symbol. These are found by looking for
+/// `obj-$(CONFIG_NAME) += module.o` in the kbuild files next to the symbol's Kconfig definition.
+fn module_names(symbol: &Symbol) -> Vec<String> {
+    let (Some(name), Some((kconfig, _))) = (symbol.name(), symbol.location()) else {
+        return Vec::new();
+    };
+    let Some(dir) = kconfig.parent() else {
+        return Vec::new();
+    };
+
+    let target = format!("obj-$(CONFIG_{})", name);
+    let mut modules = Vec::new();
+    for kbuild in ["Makefile", "Kbuild"] {
+        let Result::Ok(content) = fs::read_to_string(dir.join(kbuild)) else {
+            continue;
+        };
+        for line in content.replace("\\\n", " ").lines() {
+            let Some((lhs, rhs)) = line.split_once('=') else {
+                continue;
+            };
+            if lhs.trim_end_matches([':', '+']).trim() != target {
+                continue;
+            }
+            // Directories (foo/) are descended into, but don't name a module
+            modules.extend(
+                rhs.split_whitespace()
+                    .filter_map(|obj| obj.strip_suffix(".o"))
+                    .map(|module| module.rsplit('/').next().unwrap().to_string()),
+            );
+        }
+    }
+    modules
+}
+
 fn build_initramfs(args: &Args, bridge: &Bridge, config: &Config, tmpdir: &Path, out: &Path) -> Result<()> {
     let tmpdir_str = tmpdir.to_str().unwrap();
     println!("{:>12} modules to {}", "Installing".green(), tmpdir.display());
-    ensure!(Command::new("make")
+    ensure!(bridge
+        .make_command()
         .arg("modules_install")
         .arg(format!("INSTALL_MOD_PATH={}", tmpdir_str))
-        .current_dir(&args.kernel_dir)
         .status()
         .context("Failed to install modules to temporary directory")?
         .success());
 
+    let boot_modules = boot_modules(bridge, config)?.join(" ");
     let kernel_version = bridge.get_env("KERNELVERSION").unwrap();
     let replace_variables = |s: &String| -> String {
         s.replace("{INSTALL_MOD_PATH}", tmpdir_str)
+            .replace("{BOOT_MODULES}", &boot_modules)
             .replace("{KERNEL_VERSION}", &kernel_version)
             .replace("{OUTPUT}", out.to_str().unwrap())
             .replace(
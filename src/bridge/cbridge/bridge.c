@@ -206,3 +206,19 @@ size_t sym_prompt_count(struct symbol* sym) {
 	for_all_prompts(sym, prop) { ++count; }
 	return count;
 }
+
+/**
+ * Returns the Kconfig file in which the symbol is defined and stores the
+ * line of the definition in lineno, or NULL if the location is unknown.
+ * If the symbol is defined multiple times, the first definition is returned.
+ */
+const char* sym_get_location(struct symbol* sym, int* lineno) {
+	struct property* prop;
+	for (prop = sym->prop; prop; prop = prop->next) {
+		if (prop->menu && prop->menu->sym == sym && MENU_FILENAME(prop->menu)) {
+			*lineno = prop->menu->lineno;
+			return MENU_FILENAME(prop->menu);
+		}
+	}
+	return NULL;
+}
@@ -1,43 +1,68 @@
 # shebang is added dynamically, this is a bash
+#
+# The following environment variables are honored:
+#   INTERCEPTOR_SHELL         Path to this script, used by make as SHELL
+#   AUTOKERNEL_BRIDGE_CFLAGS  Extra flags for compiling autokernel_bridge.c (e.g. -DFOO=1 -Wno-error)
+#   srctree                   The kernel source tree (set by make, differs from . for out-of-tree builds)
 set -uo pipefail
 
 BRIDGE_C="scripts/kconfig/autokernel_bridge.c"
 BRIDGE_SO="scripts/kconfig/autokernel_bridge.so"
+# When building out-of-tree (O=...), we run in the output directory and
+# the kernel sources must be looked up relative to srctree.
+SRCTREE="${srctree:-.}"
 
 function die() { echo "[1;31merror[m: $*" >&2; exit 1; }
+# Identifies the inputs of a bridge build, so it is rebuilt when any of them changes
+function bridge_stamp() {
+	sha256sum "$BRIDGE_C" && echo "cflags: ${AUTOKERNEL_BRIDGE_CFLAGS:-}"
+}
 function build_bridge() {
 	umask 022
 
 	rm "$BRIDGE_SO" &>/dev/null
-	sha256sum "$BRIDGE_C" > "$BRIDGE_C".sha256 \
+	bridge_stamp > "$BRIDGE_C".sha256 \
 		|| die "Could not compute sha256 of autokernel_bridge.c"
 
 	o_files=()
-	if grep -q gnu11 Makefile; then
+	if grep -q gnu11 "$SRCTREE"/Makefile; then
 		detected_std=gnu11
 	else
 		detected_std=gnu89
 	fi
-	if grep -q 'common-objs' scripts/kconfig/Makefile; then
-		INPUTS=($(awk '/^common-objs.*:=/,/^$/' scripts/kconfig/Makefile | grep -P -o "\S+(?=\.o)"))
+	if grep -q 'common-objs' "$SRCTREE"/scripts/kconfig/Makefile; then
+		INPUTS=($(awk '/^common-objs.*:=/,/^$/' "$SRCTREE"/scripts/kconfig/Makefile | grep -P -o "\S+(?=\.o)"))
 		INPUTS+=(autokernel_bridge)
 	else
 		INPUTS=(conf zconf.tab autokernel_bridge)
 	fi
-	if grep -q "set_message_callback.*va" scripts/kconfig/lkc_proto.h; then
+	if grep -q "set_message_callback.*va" "$SRCTREE"/scripts/kconfig/lkc_proto.h; then
 		message_callback_type="const char* _a, va_list _b"
 	else
 		message_callback_type="const char* _a"
 	fi
+	# Newer kernels store the filename directly in each menu entry
+	if grep -q "const char \*filename;" "$SRCTREE"/scripts/kconfig/expr.h; then
+		menu_filename="(m)->filename"
+	else
+		menu_filename="((m)->file ? (m)->file->name : NULL)"
+	fi
 	for i in "${INPUTS[@]}"; do
 		if [[ "$i" == autokernel_bridge ]]; then
 			getenv_override="" \
 			std="gnu11"
+			# Intentionally word-split to allow passing multiple flags
+			extra_cflags=(${AUTOKERNEL_BRIDGE_CFLAGS:-})
 		else
 			getenv_override="-Dgetenv=autokernel_getenv"
 			std="$detected_std"
+			extra_cflags=()
 		fi
 
+		# Generated sources (e.g. the parser) are located in the output directory
+		src="scripts/kconfig/$i.c"
+		[[ -e "$src" ]] || src="$SRCTREE/scripts/kconfig/$i.c"
+
 		o="scripts/kconfig/$i.autokernel.o"
 		o_files+=("$o")
 		gcc -O3 -fPIC -Wp,-MMD,scripts/kconfig/."$i".o.d \
@@ -45,7 +70,9 @@ function build_bridge() {
 			-std="$std" \
 			$getenv_override \
 			"-DMESSAGE_CALLBACK_TYPE=$message_callback_type" \
-			-I ./scripts/kconfig -c -o "$o" scripts/kconfig/"$i".c \
+			"-DMENU_FILENAME(m)=$menu_filename" \
+			${extra_cflags[@]+"${extra_cflags[@]}"} \
+			-I ./scripts/kconfig -I "$SRCTREE"/scripts/kconfig -c -o "$o" "$src" \
 			|| die "Failed to compile $i for autokernel bridge!"
 	done
 
@@ -60,7 +87,7 @@ if [[ "$1" == "-c" && "$2" == "scripts/kconfig/conf "* ]]; then
 
 	# (Re)build bridge if necessary (e.g. autokernel update on preexisting kernel source with old bridge)
 	{ [[ -e "$BRIDGE_SO" ]] \
-		&& diff -q <(sha256sum "$BRIDGE_C") "$BRIDGE_C".sha256 &>/dev/null
+		&& diff -q <(bridge_stamp) "$BRIDGE_C".sha256 &>/dev/null
 	} || build_bridge
 
 	echo "[AUTOKERNEL BRIDGE]"
@@ -16,7 +16,7 @@ pub enum Terminal {
 }
 
 impl Terminal {
-    pub fn display<'a>(&'a self, bridge: &'a Bridge) -> TerminalDisplay {
+    pub fn display<'a>(&'a self, bridge: &'a Bridge) -> TerminalDisplay<'a> {
         TerminalDisplay { terminal: self, bridge }
     }
 }
@@ -69,6 +69,41 @@ impl Expr {
         exprs
     }
 
+    /// Returns all symbols referenced by this expression in order of
+    /// their first occurrence, without duplicates.
+    pub fn referenced_symbols(&self) -> Vec<*mut CSymbol> {
+        let mut symbols = Vec::new();
+        fn visit(symbols: &mut Vec<*mut CSymbol>, expr: &Expr) {
+            let mut add = |s: &*mut CSymbol| {
+                if !symbols.contains(s) {
+                    symbols.push(*s);
+                }
+            };
+            match expr {
+                Expr::Const(_) => {}
+                Expr::And(a, b) | Expr::Or(a, b) => {
+                    visit(symbols, a);
+                    visit(symbols, b);
+                }
+                Expr::Not(a) => visit(symbols, a),
+                Expr::Terminal(Terminal::Symbol(s)) => add(s),
+                Expr::Terminal(
+                    Terminal::Eq(a, b)
+                    | Terminal::Neq(a, b)
+                    | Terminal::Lth(a, b)
+                    | Terminal::Leq(a, b)
+                    | Terminal::Gth(a, b)
+                    | Terminal::Geq(a, b),
+                ) => {
+                    add(a);
+                    add(b);
+                }
+            }
+        }
+        visit(&mut symbols, self);
+        symbols
+    }
+
     pub fn eval(&self) -> Result<Tristate, EvalError> {
         macro_rules! is_tri_compatible {
             ($a: ident, $b: ident) => {
@@ -149,7 +184,7 @@ impl Expr {
         })
     }
 
-    pub fn display<'a>(&'a self, bridge: &'a Bridge) -> ExprDisplay {
+    pub fn display<'a>(&'a self, bridge: &'a Bridge) -> ExprDisplay<'a> {
         ExprDisplay { expr: self, bridge }
     }
 }
@@ -1,8 +1,8 @@
-use anyhow::{ensure, Context, Error, Result};
+use anyhow::{anyhow, ensure, Context, Error, Result};
 use colored::Colorize;
-use libc::c_char;
-use std::cell::RefCell;
-use std::collections::HashMap;
+use libc::{c_char, c_int};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::ffi::{CStr, CString};
 use std::io::prelude::*;
 use std::os::unix::fs::OpenOptionsExt;
@@ -10,6 +10,9 @@ use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::time::Instant;
 use std::{fs, io};
+use tempfile::NamedTempFile;
+
+use crate::script::Script;
 
 pub mod satisfier;
 mod transaction;
@@ -20,7 +23,7 @@ mod symbol;
 pub use symbol::*;
 
 mod expr;
-pub use expr::Expr;
+pub use expr::{Expr, Terminal};
 
 pub mod types;
 use types::*;
@@ -29,13 +32,95 @@ pub use types::{SymbolValue, Tristate};
 mod vtable;
 use vtable::*;
 
+mod snapshot_bridge;
+pub use snapshot_bridge::{SnapshotBridge, SymbolInfo};
+
+/// Calls a function of the kconfig library that returns 0 on success. On failure,
+/// the returned error is the OS error (errno) that caused it, if there was one.
+fn call_with_errno(f: impl FnOnce() -> c_int) -> Result<()> {
+    unsafe { *libc::__errno_location() = 0 };
+    if f() == 0 {
+        return Ok(());
+    }
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(0) | None => Err(anyhow!("the kconfig library reported an error")),
+        _ => Err(err.into()),
+    }
+}
+
+/// The value of a symbol before and after some change
+pub type ValueChange = (String, SymbolValue, SymbolValue);
+
+/// A snapshot of all symbol values that were explicitly assigned, which
+/// can later be restored to undo any changes made in the meantime.
+pub struct Snapshot {
+    file: NamedTempFile,
+}
+
+/// Options that influence how the bridge is built
+#[derive(Debug, Default, Clone)]
+pub struct BridgeOptions {
+    /// The bash executable to use in the shebang of generated scripts
+    pub bash: Option<String>,
+    /// A separate, writable directory for all generated files, which is passed to make as `O=`.
+    /// This allows the kernel directory to be read-only.
+    pub build_dir: Option<PathBuf>,
+    /// Additional flags for compiling the bridge, e.g. to provide compatibility defines
+    pub bridge_cflags: Option<String>,
+    /// The architecture to configure the kernel for, passed to make as `ARCH=`.
+    /// Defaults to the architecture of the host.
+    pub arch: Option<String>,
+}
+
+/// Everything that was read by a script, which its result may depend on
+#[derive(Debug, Default, Clone)]
+pub struct ReadSet {
+    /// Symbols whose value was read
+    pub symbols: BTreeSet<String>,
+    /// Environment variables of the kernel build that were read
+    pub env: BTreeSet<String>,
+}
+
+/// The outcome of [`Bridge::apply_atomic`]
+#[derive(Debug)]
+pub enum AtomicApply {
+    /// All assignments succeeded and were kept
+    Applied,
+    /// At least one assignment failed (or the script itself failed), so all changes
+    /// were undone. Contains the history of the script so it can be inspected.
+    RolledBack {
+        history: Vec<Transaction>,
+        error: Option<Error>,
+    },
+}
+
 #[derive(Debug)]
 pub struct Bridge {
     #[allow(dead_code)]
     vtable: BridgeVTable,
     pub kernel_dir: PathBuf,
+    /// The canonicalized build directory, if the kernel is built out-of-tree
+    pub build_dir: Option<PathBuf>,
+    /// The architecture given to make, if it isn't the one of the host
+    pub arch: Option<String>,
 
     pub history: RefCell<Vec<Transaction>>,
+    /// Symbols and environment variables that were read by scripts
+    pub read_set: RefCell<ReadSet>,
+    /// Weak assignments that are applied after all explicit ones, see [`Bridge::apply_weak_assignments`]
+    pub weak_assignments: RefCell<Vec<WeakAssignment>>,
+    /// The number of warnings that were reported while working with this bridge
+    pub n_warnings: Cell<usize>,
+    /// Abort script execution on the first failed assignment instead
+    /// of continuing and reporting all errors at the end
+    pub fail_fast: bool,
+    /// Print additional information, e.g. how values were interpreted
+    pub verbose: bool,
+    /// Show the Kconfig definition of each symbol whose assignment failed
+    pub show_kconfig_source: bool,
+    /// If set, only tracked assignments to these symbols are applied, all others are skipped
+    pub allowlist: Option<HashSet<String>>,
 
     pub symbols: Vec<*mut CSymbol>,
     pub name_to_symbol: HashMap<String, *mut CSymbol>,
@@ -46,7 +131,26 @@ impl Bridge {
     /// load it and associated functions and create and return a
     /// Bridge object to interface with the C part.
     pub fn new(kernel_dir: PathBuf, bash: Option<&str>) -> Result<Bridge> {
-        let (library_path, env) = prepare_bridge(&kernel_dir, bash)
+        Bridge::with_options(
+            kernel_dir,
+            BridgeOptions {
+                bash: bash.map(|b| b.to_string()),
+                ..BridgeOptions::default()
+            },
+        )
+    }
+
+    /// Like [`Bridge::new`], but allows to customize how the bridge is built.
+    pub fn with_options(kernel_dir: PathBuf, options: BridgeOptions) -> Result<Bridge> {
+        let build_dir = match &options.build_dir {
+            Some(build_dir) => {
+                fs::create_dir_all(build_dir)
+                    .context(format!("Could not create build directory {}", build_dir.display()))?;
+                Some(fs::canonicalize(build_dir)?)
+            }
+            None => None,
+        };
+        let (library_path, env) = prepare_bridge(&kernel_dir, build_dir.as_ref(), &options)
             .context(format!("Could not prepare bridge in {}", kernel_dir.display()))?;
 
         let time_start = Instant::now();
@@ -85,9 +189,18 @@ impl Bridge {
         let bridge = Bridge {
             vtable,
             kernel_dir,
+            build_dir,
+            arch: options.arch,
             symbols,
             name_to_symbol,
             history: RefCell::new(Vec::new()),
+            read_set: RefCell::new(ReadSet::default()),
+            weak_assignments: RefCell::new(Vec::new()),
+            n_warnings: Cell::new(0),
+            fail_fast: false,
+            verbose: false,
+            show_kconfig_source: false,
+            allowlist: None,
         };
         let n_valid_symbols = bridge
             .symbols
@@ -104,17 +217,87 @@ impl Bridge {
         Ok(bridge)
     }
 
-    pub fn wrap_symbol(&self, symbol: *mut CSymbol) -> Symbol {
+    /// Counts all named, non-constant symbols by their type
+    pub fn symbol_count_by_type(&self) -> BTreeMap<SymbolType, usize> {
+        let mut counts = BTreeMap::new();
+        for symbol in &self.symbols {
+            let symbol = unsafe { &**symbol };
+            if !symbol.is_const() && !symbol.name.is_null() {
+                *counts.entry(symbol.symbol_type()).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+
+    /// Returns the number of symbols as reported by the C bridge
+    pub fn symbol_count(&self) -> usize {
+        (self.vtable.c_symbol_count)()
+    }
+
+    /// The directory where the kernel's build outputs (like .config) are placed
+    pub fn output_dir(&self) -> &Path {
+        self.build_dir.as_deref().unwrap_or(&self.kernel_dir)
+    }
+
+    /// Returns a make command that runs in the kernel directory
+    /// and places all outputs in the build directory, if any.
+    pub fn make_command(&self) -> Command {
+        let mut command = Command::new("make");
+        command.current_dir(&self.kernel_dir);
+        if let Some(build_dir) = &self.build_dir {
+            command.arg(format!("O={}", build_dir.display()));
+        }
+        if let Some(arch) = &self.arch {
+            command.arg(format!("ARCH={}", arch));
+        }
+        command
+    }
+
+    pub fn wrap_symbol(&self, symbol: *mut CSymbol) -> Symbol<'_> {
         Symbol {
             c_symbol: symbol,
             bridge: self,
         }
     }
 
-    pub fn symbol(&self, name: &str) -> Option<Symbol> {
+    pub fn symbol(&self, name: &str) -> Option<Symbol<'_>> {
         self.name_to_symbol.get(name).map(|s| self.wrap_symbol(*s))
     }
 
+    /// Like [`Bridge::symbol`], but returns an error that distinguishes symbols that
+    /// don't exist from symbols that exist but were skipped due to their unknown type.
+    pub fn try_symbol(&self, name: &str) -> Result<Symbol<'_>> {
+        if let Some(symbol) = self.symbol(name) {
+            return Ok(symbol);
+        }
+        ensure!(
+            !self.unknown_symbols().iter().any(|s| s.name().as_deref() == Some(name)),
+            "Symbol {} exists, but has an unknown type and cannot be used",
+            name
+        );
+        Err(anyhow!("Symbol {} does not exist", name))
+    }
+
+    /// Returns all symbols that have any of the given flags set, including
+    /// unnamed symbols (such as most choices) and symbols of unknown type.
+    pub fn symbols_with_flag(&self, flag: SymbolFlags) -> Vec<Symbol<'_>> {
+        self.symbols
+            .iter()
+            .map(|s| self.wrap_symbol(*s))
+            .filter(|s| s.flags().intersects(flag))
+            .collect()
+    }
+
+    /// Returns all named, non-constant symbols that have an unknown type.
+    /// These are skipped when loading the symbols and cannot be assigned.
+    pub fn unknown_symbols(&self) -> Vec<Symbol<'_>> {
+        self.symbols
+            .iter()
+            .map(|s| self.wrap_symbol(*s))
+            .filter(|s| s.symbol_type() == SymbolType::Unknown && !s.is_const() && s.name().is_some())
+            .collect()
+    }
+
     /// Saves all modified (unsaved) values
     /// Iterates over all symbols and recalculates them
     pub fn recalculate_all_symbols(&self) {
@@ -137,7 +320,26 @@ impl Bridge {
 
     pub fn write_config(&self, path: impl AsRef<Path>) -> Result<()> {
         let c: CString = CString::new(path.as_ref().to_str().context("Invalid filename")?)?;
-        ensure!((self.vtable.c_conf_write)(c.as_ptr()) == 0, "Could not write config");
+        call_with_errno(|| (self.vtable.c_conf_write)(c.as_ptr()))
+            .with_context(|| format!("Could not write config to {}", path.as_ref().display()))
+    }
+
+    /// Writes a minimal config like `make savedefconfig` would, which only contains
+    /// symbols whose value differs from their default.
+    pub fn write_defconfig(&self, path: impl AsRef<Path>) -> Result<()> {
+        let c: CString = CString::new(path.as_ref().to_str().context("Invalid filename")?)?;
+        call_with_errno(|| (self.vtable.c_conf_write_defconfig)(c.as_ptr()))
+            .with_context(|| format!("Could not write defconfig to {}", path.as_ref().display()))
+    }
+
+    /// Reads the given .config file without checking the assignments. The kernel's conf_read
+    /// already recalculates all symbols after reading, but if `recalculate` is set, this is
+    /// done once more explicitly, so derived values are guaranteed to be up-to-date.
+    pub fn read_config(&self, path: impl AsRef<Path>, recalculate: bool) -> Result<()> {
+        self.read_config_unchecked(path)?;
+        if recalculate {
+            self.recalculate_all_symbols();
+        }
         Ok(())
     }
 
@@ -151,6 +353,157 @@ impl Bridge {
         Ok(())
     }
 
+    /// Returns the current values of all named, non-constant symbols
+    pub fn symbol_values(&self) -> BTreeMap<String, SymbolValue> {
+        self.name_to_symbol
+            .iter()
+            .map(|(name, symbol)| (name, self.wrap_symbol(*symbol)))
+            .filter(|(_, symbol)| !symbol.is_const())
+            .filter_map(|(name, symbol)| Some((name.clone(), symbol.get_value().ok()?)))
+            .collect()
+    }
+
+    /// Saves the user assigned values of all symbols. Values that
+    /// were never assigned stay unassigned when the snapshot is restored,
+    /// so they can still follow their defaults.
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        let prefix = self.get_env("CONFIG_").unwrap_or_else(|| "CONFIG_".to_string());
+        let mut file = NamedTempFile::new().context("Could not create snapshot file")?;
+        for symbol in &self.symbols {
+            if let Some(line) = unsafe { &**symbol }.user_value_config_line(&prefix) {
+                writeln!(file, "{}", line)?;
+            }
+        }
+        file.flush()?;
+        Ok(Snapshot { file })
+    }
+
+    /// Restores all symbol values to the state saved in the given snapshot
+    pub fn restore(&self, snapshot: &Snapshot) -> Result<()> {
+        self.read_config_unchecked(snapshot.file.path())
+            .context("Could not restore snapshot")
+    }
+
+    /// Resets the bridge to the state right after it was created: All symbols are
+    /// reverted to their default values, and the history, read set and warnings are cleared.
+    /// This allows applying several independent configs with a single bridge.
+    pub fn fresh_config(&self) -> Result<()> {
+        self.read_config_unchecked("/dev/null")
+            .context("Could not reset symbols to their defaults")?;
+        self.history.borrow_mut().clear();
+        *self.read_set.borrow_mut() = ReadSet::default();
+        self.weak_assignments.borrow_mut().clear();
+        self.n_warnings.set(0);
+        Ok(())
+    }
+
+    /// Counts a warning that was just reported, so it can be included in summaries
+    pub fn count_warning(&self) {
+        self.n_warnings.set(self.n_warnings.get() + 1);
+    }
+
+    /// Applies all recorded weak assignments whose symbol was not assigned explicitly, and
+    /// clears them. Scripts call this once all of their explicit assignments were made, so
+    /// the result does not depend on the order of weak and explicit assignments.
+    pub fn apply_weak_assignments(&self) -> Result<(), SymbolSetError> {
+        for weak in self.weak_assignments.take() {
+            let explicit = self
+                .history
+                .borrow()
+                .iter()
+                .rev()
+                .find(|t| t.symbol == weak.symbol && !t.weak)
+                .map(|t| format!("{}:{}", t.file, t.line));
+            if let Some(explicit) = explicit {
+                println!(
+                    "{:>12} weak assignment {}={} at {}:{} (by {})",
+                    "Overridden".yellow(),
+                    weak.symbol,
+                    weak.value,
+                    weak.file,
+                    weak.line,
+                    explicit
+                );
+                continue;
+            }
+
+            let Some(mut symbol) = self.symbol(&weak.symbol) else {
+                continue;
+            };
+            let ret = symbol.set_value_tracked_with_strength(weak.value, weak.file, weak.line, weak.traceback, true);
+            // Errors are tracked in the history and reported later,
+            // unless we are supposed to stop at the first one.
+            if self.fail_fast {
+                ret?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the given script with all-or-nothing semantics. If any assignment fails,
+    /// the previous state is restored and the transactions of the script are removed
+    /// from the history and returned instead.
+    pub fn apply_atomic(&self, script: &dyn Script) -> Result<AtomicApply> {
+        let snapshot = self.snapshot()?;
+        let history_len = self.history.borrow().len();
+        let read_set = self.read_set.borrow().clone();
+        let ret = script.apply(self);
+        let any_failed = self.history.borrow()[history_len..].iter().any(|t| t.error.is_some());
+        if ret.is_ok() && !any_failed {
+            return Ok(AtomicApply::Applied);
+        }
+
+        self.restore(&snapshot)?;
+        *self.read_set.borrow_mut() = read_set;
+        let history = self.history.borrow_mut().split_off(history_len);
+        Ok(AtomicApply::RolledBack {
+            history,
+            error: ret.err(),
+        })
+    }
+
+    /// Returns all symbols that are transitively referenced by the dependencies of the given
+    /// symbol, optionally limited to the given depth. A depth of 1 only includes the direct
+    /// dependencies. The symbol itself is not included, and constant symbols are ignored.
+    pub fn dependency_closure(&self, name: &str, max_depth: Option<usize>) -> HashSet<String> {
+        let mut closure = HashSet::new();
+        let mut queue = VecDeque::from([(name.to_string(), 0)]);
+        while let Some((current, depth)) = queue.pop_front() {
+            if max_depth.is_some_and(|max_depth| depth >= max_depth) {
+                continue;
+            }
+            let Some(Ok(Some(expr))) = self.symbol(&current).map(|s| s.visibility_expression_bare()) else {
+                continue;
+            };
+            for referenced in expr.referenced_symbols() {
+                let referenced = self.wrap_symbol(referenced);
+                if referenced.is_const() {
+                    continue;
+                }
+                if let Some(referenced) = referenced.name_owned() {
+                    if referenced != name && closure.insert(referenced.clone()) {
+                        queue.push_back((referenced, depth + 1));
+                    }
+                }
+            }
+        }
+        closure
+    }
+
+    /// Determines which symbols would change their value if the given symbol was set
+    /// to the given value. The previous state is restored afterwards.
+    pub fn impact_of(&self, name: &str, value: SymbolValue) -> Result<Vec<ValueChange>> {
+        let mut symbol = self.try_symbol(name)?;
+        let before = self.symbol_values();
+        let snapshot = self.snapshot()?;
+        let ret = symbol.set_value(value);
+        let after = self.symbol_values();
+        self.restore(&snapshot)?;
+
+        ret.context(format!("Could not assign {}", name))?;
+        Ok(diff_symbol_values(&before, &after))
+    }
+
     pub fn get_env(&self, name: &str) -> Option<String> {
         let param = CString::new(name).unwrap();
         let ret = (self.vtable.c_get_env)(param.as_ptr());
@@ -162,10 +515,40 @@ impl Bridge {
     }
 }
 
-/// Compile (or find existing) bridge shared library.
-fn prepare_bridge(kernel_dir: &PathBuf, bash: Option<&str>) -> Result<(PathBuf, EnvironMap)> {
+/// Returns all symbols that have a different value in `after` than in `before`.
+/// Symbols that only exist in one of both are ignored.
+pub fn diff_symbol_values(
+    before: &BTreeMap<String, SymbolValue>,
+    after: &BTreeMap<String, SymbolValue>,
+) -> Vec<ValueChange> {
+    before
+        .iter()
+        .filter_map(|(name, value_before)| {
+            let value_after = after.get(name)?;
+            (value_before != value_after).then(|| (name.clone(), value_before.clone(), value_after.clone()))
+        })
+        .collect()
+}
+
+/// Compile (or find existing) bridge shared library. If a build directory is given,
+/// all generated files are placed there and the kernel directory is left untouched.
+fn prepare_bridge(
+    kernel_dir: &PathBuf,
+    build_dir: Option<&PathBuf>,
+    options: &BridgeOptions,
+) -> Result<(PathBuf, EnvironMap)> {
     let time_start = Instant::now();
-    let kconfig_dir = kernel_dir.join("scripts").join("kconfig");
+    let kconfig_dir = build_dir.unwrap_or(kernel_dir).join("scripts").join("kconfig");
+    if build_dir.is_some() {
+        fs::create_dir_all(&kconfig_dir).context(format!("Could not create {}", kconfig_dir.display()))?;
+    } else {
+        let c_kconfig_dir = CString::new(kconfig_dir.to_str().context("Invalid kernel directory")?)?;
+        ensure!(
+            unsafe { libc::access(c_kconfig_dir.as_ptr(), libc::W_OK) } == 0,
+            "{} is not writable. If your kernel source is read-only, use --build-dir to build out-of-tree",
+            kconfig_dir.display()
+        );
+    }
 
     // Copy bridge.c to kernel scripts directory
     let kconfig_bridge_c = kconfig_dir.join("autokernel_bridge.c");
@@ -199,7 +582,7 @@ fn prepare_bridge(kernel_dir: &PathBuf, bash: Option<&str>) -> Result<(PathBuf,
         .open(&kconfig_interceptor_sh)
         .context(format!("Could not open {}", kconfig_interceptor_sh.display()))?;
 
-    let shebang = format!("#!{}\n", bash.unwrap_or("/usr/bin/env bash"));
+    let shebang = format!("#!{}\n", options.bash.as_deref().unwrap_or("/usr/bin/env bash"));
     interceptor_file.write_all(shebang.as_bytes())?;
     interceptor_file.write_all(include_bytes!("cbridge/interceptor.sh"))?;
     interceptor_file.flush()?;
@@ -214,10 +597,23 @@ fn prepare_bridge(kernel_dir: &PathBuf, bash: Option<&str>) -> Result<(PathBuf,
     print!("{:>12} bridge for {}\r", "Building".cyan(), kernel_dir.display());
     io::stdout().flush().unwrap();
     let bridge_library = kconfig_dir.join("autokernel_bridge.so");
+    let mut make_args = Vec::new();
+    if let Some(build_dir) = build_dir {
+        make_args.push(format!("O={}", build_dir.display()));
+    }
+    if let Some(arch) = &options.arch {
+        make_args.push(format!("ARCH={}", arch));
+    }
     let builder_output = Command::new("bash")
         .args(["-c", "--"])
-        .arg("umask 022 && make SHELL=\"$INTERCEPTOR_SHELL\" defconfig")
+        .arg("umask 022 && make SHELL=\"$INTERCEPTOR_SHELL\" \"$@\" defconfig")
+        .arg("bash")
+        .args(make_args)
         .env("INTERCEPTOR_SHELL", interceptor_shell)
+        .env(
+            "AUTOKERNEL_BRIDGE_CFLAGS",
+            options.bridge_cflags.as_deref().unwrap_or(""),
+        )
         .current_dir(kernel_dir)
         .stderr(Stdio::inherit())
         .output()?;
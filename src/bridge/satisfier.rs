@@ -4,6 +4,7 @@ use std::collections::{HashMap, HashSet, VecDeque};
 use super::types::SymbolType;
 use super::{expr::EvalError, expr::Terminal, Expr};
 use super::{Bridge, Symbol, Tristate};
+use colored::Colorize;
 use thiserror::Error;
 
 pub type Assignments = HashMap<String, Tristate>;
@@ -44,6 +45,12 @@ pub struct SolverConfig {
     pub solver: Box<dyn Solver>,
     pub desired_value: Tristate,
     pub recursive: bool,
+    /// Whether to additionally try to satisfy the visibility of symbols implied
+    /// by the target symbol. These are treated as weak preferences, so failures
+    /// and conflicts are ignored and just cause the implied symbol to be skipped.
+    pub implies: bool,
+    /// Print the intermediate state of the solver to stderr if solving fails
+    pub debug: bool,
 }
 
 impl Default for SolverConfig {
@@ -52,28 +59,135 @@ impl Default for SolverConfig {
             solver: Box::new(SimpleSolver {}),
             desired_value: Tristate::Yes,
             recursive: false,
+            implies: false,
+            debug: false,
         }
     }
 }
 
+/// A single assignment of a [`SatisfyPlan`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SatisfyStep {
+    pub symbol: String,
+    pub value: Tristate,
+    /// The symbol whose dependencies required this assignment
+    pub required_for: String,
+    /// Whether this assignment is required by the target symbol itself,
+    /// as opposed to being a prerequisite of another assignment.
+    pub direct: bool,
+}
+
+/// An ordered list of assignments that satisfies the dependencies of a target symbol.
+/// Every step only depends on assignments that come before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SatisfyPlan {
+    pub target: String,
+    pub steps: Vec<SatisfyStep>,
+}
+
+impl SatisfyPlan {
+    /// Returns the plain assignments in the order they must be applied
+    pub fn assignments(&self) -> Vec<(String, Tristate)> {
+        self.steps.iter().map(|s| (s.symbol.clone(), s.value)).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
 pub fn satisfy(bridge: &Bridge, symbol: String, config: SolverConfig) -> Result<Vec<(String, Tristate)>, SolveError> {
-    let mut assignments: Vec<(String, Tristate)> = Vec::new();
-    let mut ambiguities = Vec::new();
+    satisfy_plan(bridge, symbol, config).map(|plan| plan.assignments())
+}
+
+pub fn satisfy_plan(bridge: &Bridge, symbol: String, config: SolverConfig) -> Result<SatisfyPlan, SolveError> {
+    let mut state = SolverState::default();
+    let result = solve(bridge, symbol, &config, &mut state);
+    if let Err(err) = &result {
+        if config.debug {
+            state.dump(err);
+        }
+    }
+    result
+}
+
+/// The intermediate state of the solver, which can be dumped for debugging if solving fails
+#[derive(Default)]
+struct SolverState {
+    /// Symbols whose dependencies were already visited
+    done: HashSet<String>,
+    /// Tracks which other symbols this symbol depends on
+    dependencies: HashMap<String, Vec<String>>,
+    /// symbol -> assignments
+    solved_symbols: HashMap<String, Assignments>,
+    /// All assignments of solved_symbols, merged to detect collisions
+    merged_assignments: Assignments,
+    ambiguities: Vec<Ambiguity>,
+    /// The symbol that was being solved most recently
+    current: Option<String>,
+}
 
-    // Tracks which other symbols this symbol depends on
-    let mut dependencies = HashMap::<String, Vec<String>>::new();
-    // symbol -> assignments
-    let mut solved_symbols = HashMap::new();
+impl SolverState {
+    fn dump(&self, err: &SolveError) {
+        let format_assignments = |assignments: &Assignments| {
+            assignments
+                .iter()
+                .sorted()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .join(", ")
+        };
 
-    let mut done = HashSet::new();
+        eprintln!("{}: solver state after failure ({})", "debug".cyan().bold(), err);
+        eprintln!("   {}", "|".blue());
+        eprintln!(
+            "   {} current symbol: {}",
+            "|".blue(),
+            self.current.as_deref().unwrap_or("<none>")
+        );
+        eprintln!("   {} done: {}", "|".blue(), self.done.iter().sorted().join(", "));
+        eprintln!("   {} dependencies:", "|".blue());
+        for (symbol, depends_on) in self.dependencies.iter().sorted() {
+            eprintln!("   {}   {} -> [{}]", "|".blue(), symbol, depends_on.join(", "));
+        }
+        eprintln!("   {} solved symbols:", "|".blue());
+        for (symbol, assignments) in self.solved_symbols.iter().sorted_by_key(|(k, _)| *k) {
+            eprintln!("   {}   {}: {}", "|".blue(), symbol, format_assignments(assignments));
+        }
+        eprintln!(
+            "   {} merged assignments: {}",
+            "|".blue(),
+            format_assignments(&self.merged_assignments)
+        );
+        eprintln!("   {} ambiguities:", "|".blue());
+        for ambiguity in &self.ambiguities {
+            eprintln!(
+                "   {}   {}: {}",
+                "|".blue(),
+                ambiguity.symbol,
+                ambiguity.clauses.join(" || ")
+            );
+        }
+        eprintln!("   {}", "|".blue());
+    }
+}
+
+fn solve(
+    bridge: &Bridge,
+    symbol: String,
+    config: &SolverConfig,
+    state: &mut SolverState,
+) -> Result<SatisfyPlan, SolveError> {
+    let mut steps: Vec<SatisfyStep> = Vec::new();
     let mut queue = VecDeque::new();
+    let target = symbol.clone();
     queue.push_back(symbol);
 
     while let Some(symbol) = queue.pop_front() {
         // Skip symbols that were already satisfied
-        if !done.insert(symbol.clone()) {
+        if !state.done.insert(symbol.clone()) {
             continue;
         }
+        state.current = Some(symbol.clone());
 
         let bridge_symbol = bridge.symbol(&symbol).ok_or(SolveError::InvalidSymbol)?;
         let expr = bridge_symbol
@@ -103,7 +217,7 @@ pub fn satisfy(bridge: &Bridge, symbol: String, config: SolverConfig) -> Result<
                             // information to later return an aggregated error. Therefore we
                             // continue with Const(true) to assume that this is already solved.
                             _ => {
-                                ambiguities.push(Ambiguity {
+                                state.ambiguities.push(Ambiguity {
                                     symbol: symbol.clone(),
                                     clauses: clauses.into_iter().map(|x| x.display(bridge).to_string()).collect_vec(),
                                 });
@@ -130,24 +244,25 @@ pub fn satisfy(bridge: &Bridge, symbol: String, config: SolverConfig) -> Result<
         // Remove assignments to unassignable symbols, but only after adding
         // them to our dependencies (depends_on).
         new_assignments.retain(|k, _| bridge.symbol(k).unwrap().prompt_count() > 0);
-        solved_symbols.insert(symbol.clone(), new_assignments);
+        state.solved_symbols.insert(symbol.clone(), new_assignments);
         if !config.recursive {
-            dependencies.insert(symbol.clone(), vec![]);
+            state.dependencies.insert(symbol.clone(), vec![]);
             break;
         }
 
         queue.extend(depends_on.iter().cloned());
-        dependencies.insert(symbol.clone(), depends_on);
+        state.dependencies.insert(symbol.clone(), depends_on);
     }
 
     // Temporarily merge all assignments into a hashmap to detect collisions
-    let mut merged_assignments = HashMap::new();
-    for ass in solved_symbols.values() {
-        merge(&mut merged_assignments, ass.clone())?;
+    for ass in state.solved_symbols.values() {
+        merge(&mut state.merged_assignments, ass.clone())?;
     }
 
     // Now collect the assignments in the correct order, such that
     // all dependencies are set before setting the symbol itself.
+    let mut solved_symbols = state.solved_symbols.clone();
+    let mut dependencies = state.dependencies.clone();
     let mut already_assigned_symbols = HashSet::new();
     while !dependencies.is_empty() {
         // Split into symbols which have their dependencies fulfilled,
@@ -158,11 +273,15 @@ pub fn satisfy(bridge: &Bridge, symbol: String, config: SolverConfig) -> Result<
         // Collect the new assignments, but only if they weren't assigned before.
         // Conflicts cannot happen, as we already tested for conflicts before.
         for fs in fulfilled_symbols.keys() {
-            solved_symbols.get_mut(fs).unwrap().drain().for_each(|e| {
-                let (k, _) = &e;
-                if !already_assigned_symbols.contains(k) {
+            solved_symbols.get_mut(fs).unwrap().drain().for_each(|(k, v)| {
+                if !already_assigned_symbols.contains(&k) {
                     already_assigned_symbols.insert(k.clone());
-                    assignments.push(e);
+                    steps.push(SatisfyStep {
+                        symbol: k,
+                        value: v,
+                        required_for: fs.clone(),
+                        direct: *fs == target,
+                    });
                 }
             });
         }
@@ -174,11 +293,64 @@ pub fn satisfy(bridge: &Bridge, symbol: String, config: SolverConfig) -> Result<
         dependencies = remaining_symbols;
     }
 
-    if !ambiguities.is_empty() {
-        return Err(SolveError::AmbiguousSolution { symbols: ambiguities });
+    if !state.ambiguities.is_empty() {
+        return Err(SolveError::AmbiguousSolution {
+            symbols: state.ambiguities.clone(),
+        });
     }
 
-    Ok(assignments)
+    if config.implies {
+        for implied in implied_symbols(bridge, &target) {
+            let Some(implied_symbol) = bridge.symbol(&implied) else {
+                continue;
+            };
+            let Ok(expr) = implied_symbol.visibility_expression() else {
+                continue;
+            };
+            let Ok(mut new_assignments) = config.solver.satisfy(bridge, &expr, config.desired_value) else {
+                continue;
+            };
+
+            new_assignments.retain(|k, _| bridge.symbol(k).unwrap().prompt_count() > 0);
+            if merge(&mut state.merged_assignments, new_assignments.clone()).is_err() {
+                continue;
+            }
+            for (k, v) in new_assignments {
+                if already_assigned_symbols.insert(k.clone()) {
+                    steps.push(SatisfyStep {
+                        symbol: k,
+                        value: v,
+                        required_for: implied.clone(),
+                        direct: false,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(SatisfyPlan { target, steps })
+}
+
+/// Returns the names of all symbols that are implied by the given symbol.
+/// Each clause of a symbol's implied expression has the form `A && cond`,
+/// where A is the implying symbol.
+fn implied_symbols(bridge: &Bridge, name: &str) -> Vec<String> {
+    bridge
+        .symbols
+        .iter()
+        .map(|s| bridge.wrap_symbol(*s))
+        .filter(|s| {
+            s.implied_expression_bare().ok().flatten().is_some_and(|expr| {
+                expr.or_clauses()
+                    .into_iter()
+                    .any(|clause| match clause.and_clauses()[0] {
+                        Expr::Terminal(Terminal::Symbol(s)) => bridge.wrap_symbol(*s).name().as_deref() == Some(name),
+                        _ => false,
+                    })
+            })
+        })
+        .filter_map(|s| s.name_owned())
+        .collect()
 }
 
 pub struct SimpleSolver {}
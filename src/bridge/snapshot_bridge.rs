@@ -0,0 +1,136 @@
+use anyhow::{ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use super::types::SymbolType;
+use super::Bridge;
+
+/// Increased whenever the file format changes incompatibly
+const SNAPSHOT_FORMAT_VERSION: u32 = 1;
+
+/// Everything that is stored about a single symbol in an exported snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SymbolInfo {
+    pub symbol_type: SymbolType,
+    pub value: String,
+    pub visibility_expression: Option<String>,
+    pub reverse_dependencies: Option<String>,
+    pub implied_expression: Option<String>,
+    /// The names of all symbols referenced by the visibility expression
+    pub dependencies: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotFile {
+    format_version: u32,
+    kernel_version: Option<String>,
+    symbols: BTreeMap<String, SymbolInfo>,
+}
+
+impl SnapshotFile {
+    fn from_bridge(bridge: &Bridge) -> Result<Self> {
+        // Expressions are stored as plain text
+        colored::control::set_override(false);
+        let symbols = bridge
+            .name_to_symbol
+            .iter()
+            .map(|(name, symbol)| (name, bridge.wrap_symbol(*symbol)))
+            .filter(|(_, symbol)| !symbol.is_const())
+            .map(|(name, symbol)| {
+                let visibility_expression = symbol.visibility_expression_bare()?;
+                let dependencies = visibility_expression
+                    .iter()
+                    .flat_map(|e| e.referenced_symbols())
+                    .map(|s| bridge.wrap_symbol(s))
+                    .filter(|s| !s.is_const())
+                    .filter_map(|s| s.name_owned())
+                    .collect();
+                Ok((
+                    name.clone(),
+                    SymbolInfo {
+                        symbol_type: symbol.symbol_type(),
+                        value: symbol.get_string_value(),
+                        visibility_expression: visibility_expression.map(|e| e.display(bridge).to_string()),
+                        reverse_dependencies: symbol
+                            .reverse_dependencies_bare()?
+                            .map(|e| e.display(bridge).to_string()),
+                        implied_expression: symbol.implied_expression_bare()?.map(|e| e.display(bridge).to_string()),
+                        dependencies,
+                    },
+                ))
+            })
+            .collect::<Result<BTreeMap<_, _>>>();
+        colored::control::unset_override();
+
+        Ok(SnapshotFile {
+            format_version: SNAPSHOT_FORMAT_VERSION,
+            kernel_version: bridge.get_env("KERNELVERSION"),
+            symbols: symbols.context("Could not convert symbol expressions")?,
+        })
+    }
+}
+
+impl Bridge {
+    /// Writes the names, types, values and expressions of all symbols to the given file,
+    /// which can later be loaded by a [`SnapshotBridge`] without building the bridge.
+    pub fn export_snapshot(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let snapshot = SnapshotFile::from_bridge(self)?;
+        fs::write(path, serde_json::to_string(&snapshot)?)
+            .with_context(|| format!("Could not write snapshot to {}", path.display()))
+    }
+}
+
+/// A read-only view of the symbols of a kernel, loaded from a file that was previously
+/// written by [`Bridge::export_snapshot`]. It doesn't require the kernel sources or the C bridge.
+pub struct SnapshotBridge {
+    snapshot: SnapshotFile,
+}
+
+impl SnapshotBridge {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content =
+            fs::read_to_string(path).with_context(|| format!("Could not read snapshot {}", path.display()))?;
+        let snapshot: SnapshotFile = serde_json::from_str(&content).context("Could not parse snapshot")?;
+        ensure!(
+            snapshot.format_version == SNAPSHOT_FORMAT_VERSION,
+            "Unsupported snapshot format version {} (expected {})",
+            snapshot.format_version,
+            SNAPSHOT_FORMAT_VERSION
+        );
+        Ok(SnapshotBridge { snapshot })
+    }
+
+    /// The kernel version of the bridge from which the snapshot was exported
+    pub fn kernel_version(&self) -> Option<&str> {
+        self.snapshot.kernel_version.as_deref()
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<&SymbolInfo> {
+        self.snapshot.symbols.get(name)
+    }
+
+    /// Returns all symbols sorted by name
+    pub fn symbols(&self) -> impl Iterator<Item = (&String, &SymbolInfo)> {
+        self.snapshot.symbols.iter()
+    }
+
+    pub fn symbol_count(&self) -> usize {
+        self.snapshot.symbols.len()
+    }
+
+    pub fn get_string_value(&self, name: &str) -> Option<&str> {
+        self.symbol(name).map(|s| s.value.as_str())
+    }
+
+    pub fn symbol_type(&self, name: &str) -> Option<SymbolType> {
+        self.symbol(name).map(|s| s.symbol_type)
+    }
+
+    pub fn dependencies(&self, name: &str) -> Option<&[String]> {
+        self.symbol(name).map(|s| s.dependencies.as_slice())
+    }
+}
@@ -2,15 +2,17 @@ use crate::bridge::satisfier;
 use crate::bridge::satisfier::SolverConfig;
 
 use super::expr::Expr;
-use super::satisfier::SolveError;
-use super::transaction::Transaction;
+use super::satisfier::{SatisfyPlan, SolveError};
+use super::transaction::{Transaction, WeakAssignment};
 use super::types::*;
 use super::Bridge;
 use colored::{Color, Colorize};
 use itertools::Itertools;
+use libc::c_int;
 use std::borrow::Cow;
 use std::ffi::{CStr, CString};
 use std::fmt;
+use std::path::PathBuf;
 use thiserror::Error;
 
 macro_rules! ensure {
@@ -81,6 +83,31 @@ pub enum SymbolSetError {
     AssignmentFailed,
 }
 
+impl SymbolSetError {
+    /// A short description of the kind of error, used to summarize errors
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SymbolSetError::UnknownType => "unknown type",
+            SymbolSetError::IsConst => "constant symbol",
+            SymbolSetError::IsChoice => "choice assignment",
+            SymbolSetError::InvalidInt
+            | SymbolSetError::InvalidHex
+            | SymbolSetError::InvalidTristate
+            | SymbolSetError::InvalidBoolean
+            | SymbolSetError::InvalidValue => "invalid value",
+            SymbolSetError::SatisfyFailed { .. } => "unsatisfiable",
+            SymbolSetError::UnmetDependencies { .. } => "unmet dependencies",
+            SymbolSetError::RequiredByOther { .. } => "required-by-other",
+            SymbolSetError::CannotSetManually => "cannot be set manually",
+            SymbolSetError::MustBeSelected { .. } => "must be selected",
+            SymbolSetError::InvalidVisibility { .. } => "invalid visibility",
+            SymbolSetError::ModulesNotEnabled => "modules not enabled",
+            SymbolSetError::OutOfRange { .. } => "out of range",
+            SymbolSetError::AssignmentFailed => "rejected by kernel",
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct Symbol<'a> {
     pub(super) c_symbol: *mut CSymbol,
@@ -100,6 +127,32 @@ impl<'a> Symbol<'a> {
         (self.bridge.vtable.c_sym_calc_value)(self.c_symbol);
     }
 
+    /// Interprets a string value according to the type of this symbol
+    pub fn coerce_auto_value(&self, value: &str) -> Result<SymbolValue, SymbolSetError> {
+        Ok(match self.symbol_type() {
+            SymbolType::Unknown => return Err(SymbolSetError::UnknownType),
+            SymbolType::Boolean => {
+                // Allowed "y" "n"
+                ensure!(matches!(value, "y" | "n"), SymbolSetError::InvalidBoolean);
+                SymbolValue::Boolean(value == "y")
+            }
+            SymbolType::Tristate => {
+                // Allowed "y" "m" "n"
+                SymbolValue::Tristate(value.parse::<Tristate>().map_err(|_| SymbolSetError::InvalidTristate)?)
+            }
+            SymbolType::Int => {
+                // Allowed: Any u64 integer
+                SymbolValue::Int(value.parse::<u64>().map_err(|_| SymbolSetError::InvalidInt)?)
+            }
+            SymbolType::Hex => {
+                // Allowed: Any u64 integer
+                let value = value.strip_prefix("0x").ok_or(SymbolSetError::InvalidHex)?;
+                SymbolValue::Hex(u64::from_str_radix(value, 16).map_err(|_| SymbolSetError::InvalidHex)?)
+            }
+            SymbolType::String => SymbolValue::String(value.to_string()),
+        })
+    }
+
     pub fn set_value(&mut self, value: SymbolValue) -> Result<(), SymbolSetError> {
         ensure!(!self.is_const(), SymbolSetError::IsConst);
         ensure!(!self.is_choice(), SymbolSetError::IsChoice);
@@ -165,31 +218,19 @@ impl<'a> Symbol<'a> {
         };
 
         match (self.symbol_type(), value) {
-            (SymbolType::Unknown, SymbolValue::Auto(_)) => return Err(SymbolSetError::UnknownType),
-            (SymbolType::Boolean, SymbolValue::Auto(value)) => {
-                // Allowed "y" "n"
-                ensure!(matches!(value.as_str(), "y" | "n"), SymbolSetError::InvalidBoolean);
-                self.set_value(SymbolValue::Boolean(
-                    value.parse::<Tristate>().unwrap() == Tristate::Yes,
-                ))?
+            (_, SymbolValue::Auto(value)) => {
+                let coerced = self.coerce_auto_value(&value)?;
+                if self.bridge.verbose {
+                    println!(
+                        "{:>12} '{}' as {:?} for {}",
+                        "Interpreted".cyan(),
+                        value,
+                        coerced,
+                        self.name().unwrap_or_default()
+                    );
+                }
+                self.set_value(coerced)?
             }
-            (SymbolType::Tristate, SymbolValue::Auto(value)) => {
-                // Allowed "y" "m" "n"
-                let value = value.parse::<Tristate>().map_err(|_| SymbolSetError::InvalidTristate)?;
-                self.set_value(SymbolValue::Tristate(value))?
-            }
-            (SymbolType::Int, SymbolValue::Auto(value)) => {
-                // Allowed: Any u64 integer
-                let value = value.parse::<u64>().map_err(|_| SymbolSetError::InvalidInt)?;
-                self.set_value(SymbolValue::Int(value))?
-            }
-            (SymbolType::Hex, SymbolValue::Auto(value)) => {
-                // Allowed: Any u64 integer
-                ensure!(&value[..2] == "0x", SymbolSetError::InvalidHex);
-                let value = u64::from_str_radix(&value[2..], 16).map_err(|_| SymbolSetError::InvalidHex)?;
-                self.set_value(SymbolValue::Hex(value))?
-            }
-            (SymbolType::String, SymbolValue::Auto(value)) => self.set_value(SymbolValue::String(value))?,
             (SymbolType::Boolean | SymbolType::Tristate, SymbolValue::Boolean(value)) => set_tristate(value.into())?,
             (SymbolType::Boolean, SymbolValue::Tristate(value)) if value != Tristate::Mod => set_tristate(value)?,
             (SymbolType::Tristate, SymbolValue::Tristate(value)) => set_tristate(value)?,
@@ -247,6 +288,77 @@ impl<'a> Symbol<'a> {
         line: u32,
         traceback: Option<String>,
     ) -> Result<(), SymbolSetError> {
+        self.set_value_tracked_with_strength(value, file, line, traceback, false)
+            .map(|_| ())
+    }
+
+    /// Like [`Symbol::set_value_tracked`], but the assignment is weak: It is only recorded here,
+    /// and [`Bridge::apply_weak_assignments`] applies it later if the symbol was not assigned
+    /// explicitly by then, regardless of the order of both assignments. A later weak assignment
+    /// of the same symbol replaces this one. Returns whether the assignment was recorded, i.e.
+    /// false if the symbol is not in the allowlist or was already assigned explicitly.
+    pub fn set_value_weak_tracked(
+        &mut self,
+        value: SymbolValue,
+        file: String,
+        line: u32,
+        traceback: Option<String>,
+    ) -> Result<bool, SymbolSetError> {
+        let name = self.name_owned().unwrap();
+        let explicit = self
+            .bridge
+            .history
+            .borrow()
+            .iter()
+            .rev()
+            .find(|t| t.symbol == name && !t.weak)
+            .map(|t| format!("{}:{}", t.file, t.line));
+        if let Some(explicit) = explicit {
+            println!(
+                "{:>12} weak assignment {}={} (already assigned at {})",
+                "Skipping".yellow(),
+                name,
+                value,
+                explicit
+            );
+            return Ok(false);
+        }
+        if let Some(allowlist) = &self.bridge.allowlist {
+            if !allowlist.contains(&name) {
+                println!("{:>12} {} (not in allowlist)", "Skipping".yellow(), name);
+                return Ok(false);
+            }
+        }
+
+        let mut weak_assignments = self.bridge.weak_assignments.borrow_mut();
+        weak_assignments.retain(|w| w.symbol != name);
+        weak_assignments.push(WeakAssignment {
+            symbol: name,
+            value,
+            file,
+            line,
+            traceback,
+        });
+        Ok(true)
+    }
+
+    /// Returns whether the assignment was made, i.e. false if the symbol is not in the allowlist.
+    pub(crate) fn set_value_tracked_with_strength(
+        &mut self,
+        value: SymbolValue,
+        file: String,
+        line: u32,
+        traceback: Option<String>,
+        weak: bool,
+    ) -> Result<bool, SymbolSetError> {
+        if let Some(allowlist) = &self.bridge.allowlist {
+            let name = self.name().unwrap();
+            if !allowlist.contains(name.as_ref()) {
+                println!("{:>12} {} (not in allowlist)", "Skipping".yellow(), name);
+                return Ok(false);
+            }
+        }
+
         let current_value = self.get_value().unwrap();
         let ret = self.set_value(value.clone());
         self.bridge.history.borrow_mut().push(Transaction {
@@ -258,8 +370,75 @@ impl<'a> Symbol<'a> {
             value_before: current_value,
             value_after: self.get_value().unwrap(),
             error: ret.clone().err(),
+            weak,
         });
-        ret
+        ret.map(|_| true)
+    }
+
+    /// Like [`Symbol::set_value`], but only if the symbol can currently be changed by the user.
+    /// Otherwise, nothing is done and no error is produced. Returns whether the value was set.
+    pub fn set_if_visible(&mut self, value: SymbolValue) -> Result<bool, SymbolSetError> {
+        if !self.is_visible() {
+            self.log_skipped_invisible();
+            return Ok(false);
+        }
+        self.set_value(value).map(|_| true)
+    }
+
+    /// Like [`Symbol::set_if_visible`], but tracks the transaction if the value was set.
+    pub fn set_if_visible_tracked(
+        &mut self,
+        value: SymbolValue,
+        file: String,
+        line: u32,
+        traceback: Option<String>,
+    ) -> Result<bool, SymbolSetError> {
+        if !self.is_visible() {
+            self.log_skipped_invisible();
+            return Ok(false);
+        }
+        self.set_value_tracked_with_strength(value, file, line, traceback, false)
+    }
+
+    pub(crate) fn log_skipped_invisible(&self) {
+        if self.bridge.verbose {
+            println!(
+                "{:>12} {} (not visible)",
+                "Skipping".yellow(),
+                self.name().unwrap_or_default()
+            );
+        }
+    }
+
+    /// Returns the current string value with the given suffix appended. The separator
+    /// is only inserted if the current value is not empty.
+    fn appended_string(&self, suffix: &str, separator: &str) -> SymbolValue {
+        let current = self.get_string_value();
+        if current.is_empty() {
+            SymbolValue::String(suffix.to_string())
+        } else {
+            SymbolValue::String(format!("{current}{separator}{suffix}"))
+        }
+    }
+
+    /// Appends the given suffix to the value of a string symbol,
+    /// e.g. to extend the kernel command line.
+    pub fn append_string(&mut self, suffix: &str, separator: &str) -> Result<(), SymbolSetError> {
+        let value = self.appended_string(suffix, separator);
+        self.set_value(value)
+    }
+
+    /// Like [`Symbol::append_string`], but tracks the change as a single transaction.
+    pub fn append_string_tracked(
+        &mut self,
+        suffix: &str,
+        separator: &str,
+        file: String,
+        line: u32,
+        traceback: Option<String>,
+    ) -> Result<(), SymbolSetError> {
+        let value = self.appended_string(suffix, separator);
+        self.set_value_tracked(value, file, line, traceback)
     }
 
     pub fn get_value(&self) -> Result<SymbolValue, SymbolGetError> {
@@ -273,7 +452,8 @@ impl<'a> Symbol<'a> {
                     .map_err(|_| SymbolGetError::InvalidInt)?,
             )),
             SymbolType::Hex => Ok(SymbolValue::Hex(
-                u64::from_str_radix(&self.get_string_value()[2..], 16).map_err(|_| SymbolGetError::InvalidHex)?,
+                u64::from_str_radix(self.get_string_value().get(2..).ok_or(SymbolGetError::InvalidHex)?, 16)
+                    .map_err(|_| SymbolGetError::InvalidHex)?,
             )),
             SymbolType::String => Ok(SymbolValue::String(self.get_string_value())),
         }
@@ -299,6 +479,24 @@ impl<'a> Symbol<'a> {
         (self.bridge.vtable.c_sym_prompt_count)(self.c_symbol)
     }
 
+    /// Returns the Kconfig file and line where this symbol is defined, if known.
+    /// Relative paths are resolved against the kernel directory.
+    pub fn location(&self) -> Option<(PathBuf, u32)> {
+        let mut lineno: c_int = 0;
+        let file = (self.bridge.vtable.c_sym_get_location)(self.c_symbol, &mut lineno);
+        if file.is_null() {
+            return None;
+        }
+        let file = unsafe { CStr::from_ptr(file) }.to_string_lossy();
+        Some((self.bridge.kernel_dir.join(file.as_ref()), lineno.try_into().ok()?))
+    }
+
+    /// Returns whether the user can currently change the value of this symbol,
+    /// which requires a prompt and all of its dependencies to be met.
+    pub fn is_visible(&self) -> bool {
+        !self.is_const() && !self.is_choice() && self.prompt_count() > 0 && self.visible() > Tristate::No
+    }
+
     pub fn visible(&self) -> Tristate {
         self.recalculate();
         unsafe { &*self.c_symbol }.visible
@@ -309,13 +507,22 @@ impl<'a> Symbol<'a> {
             self.is_choice(),
             "The symbol must be a choice symbol to call .choices()"
         );
-        let count = (self.bridge.vtable.c_get_choice_symbols)(self.c_symbol, std::ptr::null_mut() as *mut *mut CSymbol);
+        let count = (self.bridge.vtable.c_get_choice_symbols)(self.c_symbol, std::ptr::null_mut());
         let mut symbols = Vec::with_capacity(count);
-        (self.bridge.vtable.c_get_choice_symbols)(self.c_symbol, symbols.as_mut_ptr() as *mut *mut CSymbol);
+        (self.bridge.vtable.c_get_choice_symbols)(self.c_symbol, symbols.as_mut_ptr());
         unsafe { symbols.set_len(count) };
         Ok(symbols)
     }
 
+    /// Returns the currently selected member of this choice symbol, if any
+    pub fn selected_choice(&self) -> anyhow::Result<Option<Symbol<'a>>> {
+        Ok(self
+            .choices()?
+            .into_iter()
+            .map(|s| self.bridge.wrap_symbol(s))
+            .find(|s| s.get_tristate_value() == Tristate::Yes))
+    }
+
     pub fn get_tristate_value(&self) -> Tristate {
         unsafe { &*self.c_symbol }.get_tristate_value()
     }
@@ -338,17 +545,37 @@ impl<'a> Symbol<'a> {
             .unwrap_or(Expr::Const(false)))
     }
 
+    /// Returns the expression of all symbols that imply this symbol.
+    /// Unlike reverse dependencies, this only determines the default value
+    /// of the symbol and never forces it to a lower bound.
+    pub fn implied_expression(&self) -> Result<Expr, ExprConvertError> {
+        Ok(unsafe { &(*self.c_symbol).implied }
+            .expr()?
+            .unwrap_or(Expr::Const(false)))
+    }
+
+    /// Returns the expression of all symbols that imply this symbol
+    pub fn implied_expression_bare(&self) -> Result<Option<Expr>, ExprConvertError> {
+        unsafe { &(*self.c_symbol).implied }.expr()
+    }
+
     pub fn get_string_value(&self) -> String {
-        return unsafe { CStr::from_ptr((self.bridge.vtable.c_sym_get_string_value)(self.c_symbol)) }
+        unsafe { CStr::from_ptr((self.bridge.vtable.c_sym_get_string_value)(self.c_symbol)) }
             .to_str()
             .unwrap()
-            .to_owned();
+            .to_owned()
     }
 
     pub fn satisfy(&self, config: SolverConfig) -> Result<Vec<(String, Tristate)>, SolveError> {
         satisfier::satisfy(self.bridge, self.name_owned().ok_or(SolveError::InvalidSymbol)?, config)
     }
 
+    /// Like [`Symbol::satisfy`], but returns a plan which also records
+    /// which symbol each assignment was required for.
+    pub fn satisfy_plan(&self, config: SolverConfig) -> Result<SatisfyPlan, SolveError> {
+        satisfier::satisfy_plan(self.bridge, self.name_owned().ok_or(SolveError::InvalidSymbol)?, config)
+    }
+
     pub fn satisfy_track_error(
         &mut self,
         value: SymbolValue,
@@ -374,6 +601,7 @@ impl<'a> Symbol<'a> {
             error: Some(SymbolSetError::SatisfyFailed {
                 error: ret.clone().unwrap_err(),
             }),
+            weak: false,
         });
         ret
     }
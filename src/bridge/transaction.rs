@@ -1,14 +1,18 @@
 use std::{
-    fs::File,
+    collections::HashSet,
+    fs::{self, File},
     io::{BufRead, BufReader},
+    path::Path,
 };
 
 use crate::bridge::satisfier::{Ambiguity, SolveError};
 
-use super::{SymbolSetError, SymbolValue, Tristate};
+use super::types::SymbolFlags;
+use super::{Bridge, SymbolSetError, SymbolValue, Tristate};
 
-use anyhow::{ensure, Result};
+use anyhow::{Context, Result};
 use colored::{Color, Colorize};
+use thiserror::Error;
 
 #[derive(Debug)]
 pub struct Transaction {
@@ -28,6 +32,24 @@ pub struct Transaction {
     pub value_after: SymbolValue,
     /// Any error that occurred
     pub error: Option<SymbolSetError>,
+    /// Whether this is a weak assignment, which yields to explicit assignments of the same symbol
+    pub weak: bool,
+}
+
+/// A weak assignment that was recorded by [`Symbol::set_value_weak_tracked`](super::Symbol::set_value_weak_tracked).
+/// It is only applied by [`Bridge::apply_weak_assignments`] after all explicit assignments were made.
+#[derive(Debug, Clone)]
+pub struct WeakAssignment {
+    /// The affected symbol
+    pub symbol: String,
+    /// The intended new value for the symbol
+    pub value: SymbolValue,
+    /// The file where the assignment originated from
+    pub file: String,
+    /// The line where the assignment originated from
+    pub line: u32,
+    /// The full traceback where the assignment originated from
+    pub traceback: Option<String>,
 }
 
 fn read_line_at_location(transaction: &Transaction) -> Option<String> {
@@ -94,6 +116,73 @@ fn print_locations(mut locations: Vec<Location>) {
     eprintln!("{indent} {}", "|".blue());
 }
 
+/// The maximum number of lines shown for a Kconfig symbol definition
+const MAX_KCONFIG_SOURCE_LINES: usize = 16;
+
+/// Prints the Kconfig definition of the given symbol, from its `config` line up to the
+/// start of the next entry, so the upstream dependencies can be seen directly.
+fn print_kconfig_source(bridge: &Bridge, symbol: &str) {
+    let Some((file, line)) = bridge.symbol(symbol).and_then(|s| s.location()) else {
+        eprintln!(
+            "   {} note: the Kconfig definition of {} is unknown",
+            "=".blue(),
+            symbol.blue()
+        );
+        return;
+    };
+    let Ok(content) = fs::read_to_string(&file) else {
+        eprintln!(
+            "   {} note: {} is defined at {}:{}, but the file cannot be read",
+            "=".blue(),
+            symbol.blue(),
+            file.display(),
+            line
+        );
+        return;
+    };
+
+    let is_entry = |l: &str| {
+        let keyword = l.split_whitespace().next().unwrap_or("");
+        matches!(
+            keyword,
+            "config"
+                | "menuconfig"
+                | "choice"
+                | "endchoice"
+                | "menu"
+                | "endmenu"
+                | "if"
+                | "endif"
+                | "source"
+                | "comment"
+        )
+    };
+    let first = line.saturating_sub(1) as usize;
+    let lines = content
+        .lines()
+        .enumerate()
+        .skip(first)
+        .take(MAX_KCONFIG_SOURCE_LINES)
+        .take_while(|(i, l)| *i == first || !is_entry(l))
+        .collect::<Vec<_>>();
+    let num_col_width = format!("{}", first + lines.len()).len().max(2);
+    let indent = " ".repeat(num_col_width);
+
+    eprintln!("{}: {} is defined here", "note".green(), symbol.blue());
+    eprintln!("{indent}{} {}:{}", "-->".blue(), file.display(), line);
+    eprintln!("{indent} {}", "|".blue());
+    for (i, l) in lines {
+        eprintln!(
+            "{:>indent$} {} {}",
+            (i + 1).to_string().blue(),
+            "|".blue(),
+            l.replace('\t', "    "),
+            indent = num_col_width
+        );
+    }
+    eprintln!("{indent} {}", "|".blue());
+}
+
 fn value_change_note(transaction: &Transaction) -> String {
     if transaction.value_before == transaction.value_after {
         format!("this did not change the previous value {:?}", transaction.value_before)
@@ -154,10 +243,75 @@ pub fn print_satisfy_result(satisfying_configuration: &Result<Vec<(String, Trist
     }
 }
 
-pub fn validate_transactions(history: &[Transaction]) -> Result<()> {
+/// Assigning `m` while MODULES is disabled is a common mistake that usually affects many symbols
+/// at once. Instead of reporting each of them separately, this prints a single error that lists
+/// all affected locations and suggests to enable MODULES first.
+fn print_modules_not_enabled(history: &[Transaction], affected: &[&Transaction]) {
+    eprintln!(
+        "{}: failed to assign {} symbols to m because module support is not enabled",
+        "error".red().bold(),
+        affected.len()
+    );
+    print_locations(
+        affected
+            .iter()
+            .map(|t| Location {
+                transaction: t,
+                hints: &["requires MODULES=y"],
+                color: Color::Red,
+            })
+            .collect(),
+    );
+
+    // If MODULES was enabled after the first failing assignment, the order is the problem
+    let first = affected[0];
+    let later_modules = history
+        .iter()
+        .skip_while(|t| !std::ptr::eq(*t, first))
+        .find(|t| t.symbol == "MODULES" && t.error.is_none() && t.value_after == SymbolValue::Boolean(true));
+    match later_modules {
+        Some(t) => eprintln!(
+            "   {} help: MODULES is only enabled later at {}:{}, move this assignment before the first {} assignment",
+            "=".blue(),
+            t.file,
+            t.line,
+            "m".color(Tristate::Mod.color())
+        ),
+        None => eprintln!(
+            "   {} help: set MODULES=y before assigning any symbol to {}",
+            "=".blue(),
+            "m".color(Tristate::Mod.color())
+        ),
+    }
+    eprintln!();
+}
+
+/// Prints the number of failed assignments for each kind of error, most frequent first
+fn print_error_summary(history: &[Transaction]) {
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for kind in history.iter().filter_map(|t| t.error.as_ref()).map(|e| e.kind()) {
+        match counts.iter_mut().find(|(k, _)| *k == kind) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((kind, 1)),
+        }
+    }
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    let summary = counts
+        .iter()
+        .map(|(kind, count)| format!("{} {}", count, kind))
+        .collect::<Vec<_>>()
+        .join(", ");
+    eprintln!("{}: {}", "summary".bold(), summary);
+}
+
+pub fn validate_transactions(history: &[Transaction], bridge: &Bridge) -> Result<()> {
     let mut n_errors = 0u32;
+    let mut modules_not_enabled = Vec::new();
     for (i, t) in history.iter().enumerate() {
-        if let Some(error) = &t.error {
+        if let Some(SymbolSetError::ModulesNotEnabled) = &t.error {
+            // Reported collectively below
+            modules_not_enabled.push(t);
+        } else if let Some(error) = &t.error {
             n_errors += 1;
             eprintln!(
                 "{}: failed to assign symbol {} to {:?} at this location...",
@@ -228,12 +382,27 @@ pub fn validate_transactions(history: &[Transaction]) -> Result<()> {
                 }
                 _ => eprintln!("{}: {}", "note".green(), error),
             }
+            if bridge.show_kconfig_source {
+                print_kconfig_source(bridge, &t.symbol);
+            }
             eprintln!();
         }
 
-        // Detect re-assignments
-        for other in history[0..i].iter().rev() {
-            if other.symbol == t.symbol && t.value_before != t.value_after {
+        match find_reassignment(history, i) {
+            Some(Reassignment::OverridesWeak(other)) => {
+                println!(
+                    "{:>12} weak assignment {}={} at {}:{} (by {}:{})",
+                    "Overridden".yellow(),
+                    t.symbol,
+                    other.value,
+                    other.file,
+                    other.line,
+                    t.file,
+                    t.line
+                );
+            }
+            Some(Reassignment::Changes(other)) => {
+                bridge.count_warning();
                 eprintln!(
                     "{}: reassignment of symbol {} to {:?}",
                     "warning".yellow().bold(),
@@ -259,11 +428,93 @@ pub fn validate_transactions(history: &[Transaction]) -> Result<()> {
                     },
                 ]);
                 eprintln!();
-                break;
             }
+            None => (),
         }
     }
 
-    ensure!(n_errors == 0, "aborting due to {} previous errors", n_errors);
+    if !modules_not_enabled.is_empty() {
+        // Counted per failed assignment, so the total matches the error summary
+        n_errors += modules_not_enabled.len() as u32;
+        print_modules_not_enabled(history, &modules_not_enabled);
+    }
+
+    if n_errors > 0 {
+        print_error_summary(history);
+        return Err(ValidationError(n_errors).into());
+    }
     Ok(())
 }
+
+/// Returned by [`validate_transactions`] if any of the transactions failed.
+/// Contains the number of failed assignments.
+#[derive(Error, Debug)]
+#[error("aborting due to {0} previous errors")]
+pub struct ValidationError(pub u32);
+
+/// An earlier assignment of the same symbol that a transaction interacts with
+enum Reassignment<'a> {
+    /// Explicit assignments are supposed to override weak ones
+    OverridesWeak(&'a Transaction),
+    /// The transaction changed the value that was assigned before
+    Changes(&'a Transaction),
+}
+
+fn find_reassignment(history: &[Transaction], i: usize) -> Option<Reassignment<'_>> {
+    let t = &history[i];
+    for other in history[0..i].iter().rev() {
+        if other.symbol == t.symbol && other.weak && !t.weak {
+            return Some(Reassignment::OverridesWeak(other));
+        }
+        if other.symbol == t.symbol && t.value_before != t.value_after {
+            return Some(Reassignment::Changes(other));
+        }
+    }
+    None
+}
+
+/// Warns about all symbols that were successfully assigned but do not appear in the
+/// given written config file, and explains why. Returns the names of these symbols.
+pub fn audit_written_config(history: &[Transaction], bridge: &Bridge, path: impl AsRef<Path>) -> Result<Vec<String>> {
+    let prefix = bridge.get_env("CONFIG_").unwrap_or_else(|| "CONFIG_".to_string());
+    let content = fs::read_to_string(&path).context(format!("Could not read {}", path.as_ref().display()))?;
+    let written: HashSet<&str> = content
+        .lines()
+        .filter_map(|line| {
+            let line = line.strip_prefix("# ").unwrap_or(line);
+            line.strip_prefix(&prefix)?.split(['=', ' ']).next()
+        })
+        .collect();
+
+    // Only the last successful assignment of each symbol is relevant
+    let mut seen = HashSet::new();
+    let mut missing = Vec::new();
+    for t in history.iter().rev().filter(|t| t.error.is_none()) {
+        if !seen.insert(&t.symbol) || written.contains(t.symbol.as_str()) {
+            continue;
+        }
+
+        let flags = bridge.symbol(&t.symbol).map(|s| s.flags());
+        let reason = match flags {
+            Some(flags) if flags.intersects(SymbolFlags::NOWRITE) => "the kernel never writes this symbol",
+            Some(flags) if !flags.intersects(SymbolFlags::WRITE) => {
+                "its dependencies are not met in the final configuration"
+            }
+            _ => "of an unknown reason",
+        };
+        bridge.count_warning();
+        eprintln!(
+            "{}: symbol {} was assigned but does not appear in the written config",
+            "warning".yellow().bold(),
+            t.symbol
+        );
+        print_locations(vec![Location {
+            transaction: t,
+            hints: &[&format!("note: not written because {}", reason).yellow()],
+            color: Color::Yellow,
+        }]);
+        eprintln!();
+        missing.push(t.symbol.clone());
+    }
+    Ok(missing)
+}
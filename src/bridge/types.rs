@@ -83,7 +83,9 @@ impl fmt::Display for Tristate {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, strum::AsRefStr)]
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, strum::AsRefStr, serde::Serialize, serde::Deserialize,
+)]
 #[repr(C)]
 #[allow(dead_code)]
 pub enum SymbolType {
@@ -141,6 +143,20 @@ pub enum SymbolValue {
     Auto(String),
 }
 
+/// Formats the value like it would appear in a .config file
+impl fmt::Display for SymbolValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SymbolValue::Boolean(value) => write!(f, "{}", Tristate::from(*value)),
+            SymbolValue::Tristate(value) => write!(f, "{}", value),
+            SymbolValue::Int(value) | SymbolValue::Number(value) => write!(f, "{}", value),
+            SymbolValue::Hex(value) => write!(f, "{:#x}", value),
+            SymbolValue::String(value) => write!(f, "\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")),
+            SymbolValue::Auto(value) => write!(f, "{}", value),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 #[repr(C)]
 #[allow(dead_code)]
@@ -290,6 +306,35 @@ impl CSymbol {
     pub fn is_choice(&self) -> bool {
         self.flags.intersects(SymbolFlags::CHOICE)
     }
+
+    /// Returns the value that was explicitly assigned to this symbol formatted as a line
+    /// in a .config file, or None if the symbol has no such user value.
+    pub fn user_value_config_line(&self, prefix: &str) -> Option<String> {
+        if !self.flags.intersects(SymbolFlags::DEF_USER) {
+            return None;
+        }
+
+        let name = self.name()?;
+        // def[S_DEF_USER] holds the user value
+        let user_value = &self.default_values[0];
+        let string_value = || unsafe {
+            (user_value.value as *const c_char)
+                .as_ref()
+                .map(|obj| String::from_utf8_lossy(CStr::from_ptr(obj).to_bytes()).into_owned())
+        };
+        Some(match self.symbol_type {
+            SymbolType::Boolean | SymbolType::Tristate => match user_value.tri {
+                Tristate::No => format!("# {prefix}{name} is not set"),
+                value => format!("{prefix}{name}={value}"),
+            },
+            SymbolType::Int | SymbolType::Hex => format!("{prefix}{name}={}", string_value()?),
+            SymbolType::String => format!(
+                "{prefix}{name}=\"{}\"",
+                string_value()?.replace('\\', "\\\\").replace('"', "\\\"")
+            ),
+            SymbolType::Unknown => return None,
+        })
+    }
 }
 
 use bitflags::bitflags;
@@ -311,5 +356,6 @@ bitflags! {
         const NOWRITE   = 0x1000;
         const CHECKED   = 0x2000;
         const WARNED    = 0x8000;
+        const DEF_USER  = 0x10000;
     }
 }
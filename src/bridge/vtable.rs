@@ -20,7 +20,9 @@ pub type FuncSymIntGetMin = extern "C" fn(*mut CSymbol) -> u64;
 pub type FuncSymIntGetMax = extern "C" fn(*mut CSymbol) -> u64;
 pub type FuncSymDirectDepsWithPrompts = extern "C" fn(*mut CSymbol) -> *mut CExpr;
 pub type FuncSymPromptCount = extern "C" fn(*mut CSymbol) -> size_t;
+pub type FuncSymGetLocation = extern "C" fn(*mut CSymbol, *mut c_int) -> *const c_char;
 pub type FuncConfWrite = extern "C" fn(*const c_char) -> c_int;
+pub type FuncConfWriteDefconfig = extern "C" fn(*const c_char) -> c_int;
 pub type FuncConfReadUnchecked = extern "C" fn(*const c_char) -> c_int;
 
 #[derive(Debug)]
@@ -40,7 +42,9 @@ pub struct BridgeVTable {
     pub c_sym_int_get_max: RawSymbol<FuncSymIntGetMax>,
     pub c_sym_direct_deps_with_prompts: RawSymbol<FuncSymDirectDepsWithPrompts>,
     pub c_sym_prompt_count: RawSymbol<FuncSymPromptCount>,
+    pub c_sym_get_location: RawSymbol<FuncSymGetLocation>,
     pub c_conf_write: RawSymbol<FuncConfWrite>,
+    pub c_conf_write_defconfig: RawSymbol<FuncConfWriteDefconfig>,
     pub c_conf_read_unchecked: RawSymbol<FuncConfReadUnchecked>,
 }
 
@@ -67,7 +71,9 @@ impl BridgeVTable {
         let c_sym_direct_deps_with_prompts =
             load_symbol!(FuncSymDirectDepsWithPrompts, b"sym_direct_deps_with_prompts");
         let c_sym_prompt_count = load_symbol!(FuncSymPromptCount, b"sym_prompt_count");
+        let c_sym_get_location = load_symbol!(FuncSymGetLocation, b"sym_get_location");
         let c_conf_write = load_symbol!(FuncConfWrite, b"conf_write");
+        let c_conf_write_defconfig = load_symbol!(FuncConfWriteDefconfig, b"conf_write_defconfig");
         let c_conf_read_unchecked = load_symbol!(FuncConfReadUnchecked, b"conf_read");
 
         Ok(BridgeVTable {
@@ -85,7 +91,9 @@ impl BridgeVTable {
             c_sym_int_get_max,
             c_sym_direct_deps_with_prompts,
             c_sym_prompt_count,
+            c_sym_get_location,
             c_conf_write,
+            c_conf_write_defconfig,
             c_conf_read_unchecked,
         })
     }
@@ -95,7 +103,7 @@ impl BridgeVTable {
     pub fn get_all_symbols(&self) -> Vec<*mut CSymbol> {
         let count = (self.c_symbol_count)();
         let mut symbols = Vec::with_capacity(count);
-        (self.c_get_all_symbols)(symbols.as_mut_ptr() as *mut *mut CSymbol);
+        (self.c_get_all_symbols)(symbols.as_mut_ptr());
         unsafe { symbols.set_len(count) };
         symbols
     }
@@ -1,4 +1,4 @@
-use std::{fs, path::Path};
+use std::{collections::BTreeMap, fs, path::Path};
 
 use anyhow::{Context, Ok, Result};
 use colored::Colorize;
@@ -17,6 +17,12 @@ pub struct SectionConfig {
     pub script: String,
     #[serde(default)]
     pub install: SectionConfigInstall,
+    /// Assignments of the form SYMBOL=value that must hold in the final configuration
+    #[serde(default)]
+    pub require: Vec<String>,
+    /// Groups of symbols, of which at least one must be enabled in the final configuration
+    #[serde(default)]
+    pub require_any: Vec<Vec<String>>,
 }
 
 #[derive(Deserialize)]
@@ -44,6 +50,8 @@ pub struct SectionInitramfs {
     pub enable: bool,
     pub builtin: bool,
     pub command: Vec<String>,
+    /// Symbols whose modules are needed during early boot, passed to the command as {BOOT_MODULES}
+    pub boot_modules: Vec<String>,
     pub install: SectionInitramfsInstall,
 }
 
@@ -70,6 +78,19 @@ pub struct Config {
     pub kernel: SectionKernel,
     #[serde(default)]
     pub modules: SectionModules,
+    /// Replacements for the [config] section when configuring for a specific architecture
+    #[serde(default)]
+    pub arch: BTreeMap<String, SectionConfig>,
+}
+
+impl Config {
+    /// Replaces the [config] section by the [arch.NAME] section of the given architecture, if there is one
+    pub fn for_arch(mut self, arch: &str) -> Self {
+        if let Some(section) = self.arch.remove(arch) {
+            self.config = section;
+        }
+        self
+    }
 }
 
 impl Default for SectionConfigInstall {
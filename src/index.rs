@@ -0,0 +1,245 @@
+use crate::bridge::{Bridge, Symbol};
+
+use anyhow::{bail, ensure, Context, Ok, Result};
+use colored::Colorize;
+use rusqlite::{Connection, Transaction};
+use uuid::Uuid;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+
+pub fn create_schema(conn: &mut Connection) -> Result<()> {
+    let tx = conn.transaction()?;
+    // Create tables
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS kernel (
+            id               TEXT NOT NULL,
+            version_major    INTEGER NOT NULL,
+            version_minor    INTEGER NOT NULL,
+            version_patch    INTEGER NOT NULL,
+            name             TEXT NOT NULL,
+            UNIQUE (version_major, version_minor, version_patch, name),
+            PRIMARY KEY (id))",
+        (), // empty list of parameters.
+    )?;
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS config (
+            id               TEXT NOT NULL,
+            kernel_id        TEXT NOT NULL REFERENCES kernel(id),
+            architecture     TEXT,
+            name             TEXT NOT NULL,
+            UNIQUE (kernel_id, architecture, name),
+            PRIMARY KEY (id))",
+        (), // empty list of parameters.
+    )?;
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS symbol (
+            kernel_id        TEXT NOT NULL REFERENCES kernel(id),
+            name             TEXT NOT NULL,
+            type             TEXT NOT NULL,
+            visibility_expression TEXT,
+            reverse_dependencies  TEXT,
+            PRIMARY KEY (kernel_id, name))",
+        (), // empty list of parameters.
+    )?;
+    // Directed edges from a symbol to the symbols referenced by its expressions.
+    // For 'depends' the referenced symbol appears in the symbol's dependencies,
+    // for 'select' and 'imply' the referenced symbol selects or implies the symbol.
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS dependency (
+            kernel_id        TEXT NOT NULL REFERENCES kernel(id),
+            symbol           TEXT NOT NULL,
+            referenced       TEXT NOT NULL,
+            relation         TEXT NOT NULL CHECK (relation IN ('depends', 'select', 'imply')),
+            PRIMARY KEY (kernel_id, symbol, referenced, relation))",
+        (), // empty list of parameters.
+    )?;
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS value (
+            config_id        TEXT NOT NULL REFERENCES config(id),
+            symbol_name      TEXT NOT NULL,
+            value            TEXT NOT NULL,
+            PRIMARY KEY (config_id, symbol_name))",
+        (), // empty list of parameters.
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+pub fn is_valid_symbol(symbol: &Symbol) -> bool {
+    !symbol.is_const() && symbol.name().is_some()
+}
+
+pub fn parse_kernel_version(ver: &str) -> Result<(u32, u32, u32)> {
+    if let Some(d1) = ver.find('.') {
+        let major = ver[..d1].parse::<u32>()?;
+        let rest = &ver[d1 + 1..];
+        if let Some(d2) = rest.find('.') {
+            Ok((major, rest[..d2].parse::<u32>()?, rest[d2 + 1..].parse::<u32>()?))
+        } else {
+            Ok((major, rest.parse::<u32>()?, 0))
+        }
+    } else {
+        bail!("Cannot parse kernel version (missing .)");
+    }
+}
+
+/// Returns the name under which the kernel of the given bridge is indexed,
+/// which is the name of its source directory
+pub fn kernel_name(bridge: &Bridge) -> Result<String> {
+    // Canonicalize first, so relative paths like . and trailing slashes still have a name
+    let kernel_dir = bridge
+        .kernel_dir
+        .canonicalize()
+        .with_context(|| format!("Could not resolve kernel directory {}", bridge.kernel_dir.display()))?;
+    let name = kernel_dir
+        .file_name()
+        .and_then(|n| n.to_str())
+        .with_context(|| format!("Kernel directory {} has no valid name", kernel_dir.display()))?;
+    Ok(name.to_string())
+}
+
+/// Returns the id of the indexed kernel that the given bridge was created for
+pub fn indexed_kernel_id(conn: &Connection, bridge: &Bridge) -> Result<String> {
+    let kernel_name = kernel_name(bridge)?;
+    let version = bridge.get_env("KERNELVERSION").context("KERNELVERSION is not set")?;
+    let (v_major, v_minor, v_patch) = parse_kernel_version(&version)?;
+    let kernel_id = conn
+        .prepare("SELECT id from kernel WHERE version_major=? AND version_minor=? AND version_patch=? AND name=?")?
+        .query_row((v_major, v_minor, v_patch, &kernel_name), |row| row.get(0))
+        .with_context(|| {
+            format!(
+                "Kernel {} ({}.{}.{}) is not indexed yet, run autokernel-index kernel first",
+                kernel_name, v_major, v_minor, v_patch
+            )
+        })?;
+    Ok(kernel_id)
+}
+
+/// Indexes the current symbol values of the bridge as a new config and returns its id.
+/// If kconf is given, it is loaded before.
+pub fn index_values(
+    bridge: &Bridge,
+    tx: &Transaction,
+    kernel_id: &str,
+    name: &str,
+    kconf: Option<&PathBuf>,
+    arch: Option<&String>,
+) -> Result<String> {
+    if let Some(kconf) = kconf {
+        // Values are read directly afterwards, so make sure all derived values are current
+        bridge.read_config(kconf, true)?;
+        println!("{:>12} kconf ({})", "Loaded".green(), kconf.display());
+    }
+
+    let time_start = Instant::now();
+    let config_id = Uuid::new_v4().to_string();
+    tx.execute(
+        "INSERT INTO config VALUES (?1, ?2, ?3, ?4)",
+        (&config_id, kernel_id, arch, name),
+    )?;
+
+    print!("{:>12} symbol values...\r", "Indexing".cyan());
+    io::stdout().flush()?;
+
+    let mut n_indexed_symbols = 0;
+    for symbol in &bridge.symbols {
+        let symbol = bridge.wrap_symbol(*symbol);
+        if is_valid_symbol(&symbol) {
+            n_indexed_symbols += 1;
+
+            tx.execute(
+                "INSERT INTO value VALUES (?1, ?2, ?3)",
+                (
+                    &config_id,
+                    symbol.name().unwrap().to_string(),
+                    symbol.get_string_value(),
+                ),
+            )?;
+        }
+    }
+
+    println!(
+        "{:>12} {} symbol values [{}] in {:.2?}",
+        "Indexed".green(),
+        n_indexed_symbols,
+        name,
+        time_start.elapsed()
+    );
+    Ok(config_id)
+}
+
+/// Removes the given config and its values from the index, if it exists
+pub fn remove_config(tx: &Transaction, kernel_id: &str, name: &str, arch: Option<&String>) -> Result<()> {
+    tx.execute(
+        "DELETE FROM value WHERE config_id IN
+            (SELECT id FROM config WHERE kernel_id = ?1 AND architecture IS ?2 AND name = ?3)",
+        (kernel_id, arch, name),
+    )?;
+    tx.execute(
+        "DELETE FROM config WHERE kernel_id = ?1 AND architecture IS ?2 AND name = ?3",
+        (kernel_id, arch, name),
+    )?;
+    Ok(())
+}
+
+/// Returns the values of all symbols in the given indexed config
+pub fn config_values(conn: &Connection, config_id: &str) -> Result<BTreeMap<String, String>> {
+    let mut stmt = conn.prepare("SELECT symbol_name, value FROM value WHERE config_id = ?")?;
+    let values = stmt
+        .query_map([config_id], |row| rusqlite::Result::Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(values)
+}
+
+/// An indexed config that is part of the matrix
+pub struct MatrixEntry {
+    pub label: String,
+    pub values: BTreeMap<String, String>,
+}
+
+/// Shows all symbols whose value differs between the given matrix entries
+pub fn print_matrix(entries: &[MatrixEntry]) -> Result<()> {
+    ensure!(
+        entries.len() >= 2,
+        "At least two indexed configs are required to compare them"
+    );
+
+    println!("Matrix of {} configs:", entries.len());
+    for (i, entry) in entries.iter().enumerate() {
+        println!("  {} {}", format!("[{}]", i + 1).blue(), entry.label);
+    }
+
+    let names = entries.iter().flat_map(|e| e.values.keys()).collect::<BTreeSet<_>>();
+    let mut n_diverging = 0;
+    for name in names {
+        let values = entries.iter().map(|e| e.values.get(name)).collect::<Vec<_>>();
+        // Symbols that don't exist in some kernels are only compared where they exist
+        let mut present = values.iter().flatten();
+        let Some(first) = present.next() else {
+            continue;
+        };
+        if present.all(|v| v == first) {
+            continue;
+        }
+
+        n_diverging += 1;
+        let values = values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                format!(
+                    "[{}]={}",
+                    i + 1,
+                    v.map(|v| format!("{:?}", v)).unwrap_or_else(|| "-".into())
+                )
+            })
+            .collect::<Vec<_>>();
+        println!("  {} {} {}", "~".yellow(), name, values.join(" "));
+    }
+
+    println!("{:>12} {} symbols diverge", "Summary".green(), n_diverging);
+    Ok(())
+}
@@ -1,3 +1,5 @@
 pub mod bridge;
 pub mod config;
+#[cfg(feature = "index")]
+pub mod index;
 pub mod script;
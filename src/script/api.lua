@@ -144,6 +144,7 @@ end
 
 function Symbol:type() return ak.symbol_get_type(self.name) end
 function Symbol:str_value() return ak.symbol_get_string(self.name) end
+function Symbol:deps() return ak.symbol_deps(self.name) end
 
 function Symbol:is(value)
 	local stype = self:type()
@@ -199,6 +200,39 @@ function Symbol:set(value, dbginfo)
 	end
 end
 
+-- Sets the symbol weakly, like a default of a base profile. Weak assignments are applied after
+-- the whole script ran, and only to symbols that were not set explicitly anywhere in it, so
+-- their order doesn't matter. Returns false if the assignment will certainly be skipped.
+function Symbol:set_weak(value, dbginfo)
+	dbginfo = dbginfo or debug.getinfo(2)
+	if getmetatable(value) == Tristate then
+		value = value.name
+	elseif type(value) == "number" then
+		value = tostring(value)
+	elseif type(value) ~= "string" then
+		error ("Unsupported value type '" .. type(value) .. "'")
+	end
+	return ak.symbol_set_auto_weak(self.name, value, dbginfo.source, dbginfo.currentline, debug.traceback())
+end
+
+function Symbol:is_visible() return ak.symbol_is_visible(self.name, false) end
+function Symbol:set_if_visible(value, dbginfo)
+	dbginfo = dbginfo or debug.getinfo(2)
+	if ak.symbol_is_visible(self.name, true) then
+		self:set(value, dbginfo)
+		return true
+	end
+	return false
+end
+
+function Symbol:append(str, sep, dbginfo)
+	dbginfo = dbginfo or debug.getinfo(2)
+	if type(str) ~= "string" then
+		error ("Unsupported value type '" .. type(str) .. "', can only append strings")
+	end
+	ak.symbol_append_string(self.name, str, sep or " ", dbginfo.source, dbginfo.currentline, debug.traceback())
+end
+
 function Symbol:satisfy(tbl, dbginfo)
 	dbginfo = dbginfo or debug.getinfo(2)
 
@@ -222,3 +256,200 @@ function Symbol:satisfy(tbl, dbginfo)
 		error ("Unsupported value type '" .. type(value) .. "', must be Tristate (m or y)")
 	end
 end
+
+-- Sets a symbol like Symbol:set, but records the given location for the assignment
+-- instead of the caller's. Helper functions can use this to attribute assignments
+-- (and errors) to the place where the helper was called from, for example via
+-- `local info = debug.getinfo(2, "Sl")` and `ak.set_at(name, value, info.source, info.currentline)`.
+function ak.set_at(name, value, file, line)
+	if type(name) ~= "string" or type(file) ~= "string" or type(line) ~= "number" then
+		error("set_at requires a symbol name, a value, a file and a line number", 2)
+	end
+	name = string.gsub(name, "^CONFIG_", "")
+	local sym = rawget(_G, "CONFIG_" .. name)
+	if sym == nil then
+		error("symbol " .. name .. " does not exist", 2)
+	end
+	sym:set(value, { source = file, currentline = line })
+end
+
+-- Weakly sets the given symbol, see Symbol:set_weak. This allows base profiles to provide
+-- values like `ak.symbol_set_weak("FOO", m)`, which more specific profiles can override.
+function ak.symbol_set_weak(name, value)
+	if type(name) ~= "string" then
+		error("symbol_set_weak requires a symbol name and a value", 2)
+	end
+	name = string.gsub(name, "^CONFIG_", "")
+	local sym = rawget(_G, "CONFIG_" .. name)
+	if sym == nil then
+		error("symbol " .. name .. " does not exist", 2)
+	end
+	return sym:set_weak(value, debug.getinfo(2, "Sl"))
+end
+
+-- All existing symbols are defined as globals. Accessing an undefined CONFIG_* global
+-- therefore is most likely a typo, which we report right away instead of yielding nil.
+setmetatable(_G, {
+	__index = function(_, name)
+		if type(name) == "string" and string.sub(name, 1, 7) == "CONFIG_" then
+			error("symbol " .. string.sub(name, 8) .. " does not exist (referenced as " .. name .. "), "
+				.. "use ak.symbol_exists to check for symbols that only some kernels provide", 2)
+		end
+		return nil
+	end,
+})
+
+--###############################################################
+-- Temporary assignments
+
+-- Runs fn with the given symbols temporarily set to the given values, like
+-- `ak.with({ FOO = y }, function() ... end)`. Afterwards, all symbols are restored
+-- to their previous state, including any changes made by fn itself. This allows
+-- inspecting hypothetical states. Returns whatever fn returns.
+function ak.with(tbl, fn)
+	if type(tbl) ~= "table" or type(fn) ~= "function" then
+		error("with requires a table of values and a function, like `ak.with({ FOO = y }, function() ... end)`", 2)
+	end
+
+	ak.push_state()
+	local ok, ret = xpcall(function()
+		for name, value in pairs(tbl) do
+			if getmetatable(value) == Tristate then
+				value = value.name
+			end
+			ak.symbol_set_temporary(string.gsub(name, "^CONFIG_", ""), tostring(value))
+		end
+		return fn()
+	end, debug.traceback)
+	ak.pop_state()
+
+	if not ok then
+		error(ret, 0)
+	end
+	return ret
+end
+
+--###############################################################
+-- Aliases
+
+-- Registry of all aliases defined via ak.define_alias
+ak.aliases = { }
+
+local function location(info)
+	return info.source .. ":" .. info.currentline
+end
+
+function ak.define_alias(name, fn)
+	if type(name) ~= "string" or type(fn) ~= "function" then
+		error("define_alias requires a name and a function, like `ak.define_alias(\"NAME\", function() ... end)`", 2)
+	end
+	if ak.aliases[name] ~= nil then
+		error("alias " .. name .. " was already defined at " .. ak.aliases[name].defined_at, 2)
+	end
+	if rawget(_G, name) ~= nil then
+		error("cannot define alias " .. name .. " because this name is already in use", 2)
+	end
+
+	local alias = { fn = fn, defined_at = location(debug.getinfo(2, "Sl")) }
+	ak.aliases[name] = alias
+	_G[name] = function(...)
+		local called_at = location(debug.getinfo(2, "Sl"))
+		local ok, err = xpcall(fn, debug.traceback, ...)
+		if not ok then
+			error("in alias " .. name .. " (defined at " .. alias.defined_at .. ", called at " .. called_at .. "): " .. tostring(err), 0)
+		end
+		return err
+	end
+end
+
+--###############################################################
+-- Kernel command line
+
+-- Splits a command line into its parameters. Double quotes may be used
+-- to include spaces in a value, like `foo="a b"`.
+local function cmdline_parse(str)
+	local tokens = { }
+	local token = ""
+	local quoted = false
+	for c in string.gmatch(str, ".") do
+		if c == '"' then
+			quoted = not quoted
+			token = token .. c
+		elseif not quoted and string.match(c, "%s") then
+			if token ~= "" then
+				table.insert(tokens, token)
+			end
+			token = ""
+		else
+			token = token .. c
+		end
+	end
+	if token ~= "" then
+		table.insert(tokens, token)
+	end
+	return tokens
+end
+
+-- Returns the key of a parameter, which is everything before the first "="
+local function cmdline_key(token)
+	return string.match(token, "^([^=]*)")
+end
+
+-- Returns whether the given token matches the parameter. A parameter without "="
+-- matches every token with the same key, otherwise the whole token must match.
+local function cmdline_matches(token, param)
+	if string.find(param, "=", 1, true) then
+		return token == param
+	end
+	return cmdline_key(token) == param
+end
+
+local function cmdline_symbol()
+	local sym = rawget(_G, "CONFIG_CMDLINE")
+	if sym == nil then
+		error("the kernel command line cannot be changed, because CMDLINE does not exist for this architecture", 3)
+	end
+	return sym
+end
+
+local function cmdline_set(tokens, dbginfo)
+	ak.symbol_set_auto("CMDLINE", table.concat(tokens, " "), dbginfo.source, dbginfo.currentline, debug.traceback())
+end
+
+-- Adds a parameter to CONFIG_CMDLINE. If a parameter with the same key
+-- already exists (e.g. root=...), it is replaced instead.
+function ak.cmdline_add(param)
+	local dbginfo = debug.getinfo(2)
+	local tokens = cmdline_parse(cmdline_symbol():str_value())
+	local key = cmdline_key(param)
+	for i, token in ipairs(tokens) do
+		if cmdline_key(token) == key then
+			tokens[i] = param
+			return cmdline_set(tokens, dbginfo)
+		end
+	end
+	table.insert(tokens, param)
+	cmdline_set(tokens, dbginfo)
+end
+
+-- Removes all parameters with the given key (or exactly matching key=value) from CONFIG_CMDLINE
+function ak.cmdline_remove(param)
+	local dbginfo = debug.getinfo(2)
+	local tokens = { }
+	for _, token in ipairs(cmdline_parse(cmdline_symbol():str_value())) do
+		if not cmdline_matches(token, param) then
+			table.insert(tokens, token)
+		end
+	end
+	cmdline_set(tokens, dbginfo)
+end
+
+-- Returns whether CONFIG_CMDLINE contains a parameter with the given key (or exactly matching key=value)
+function ak.cmdline_has(param)
+	for _, token in ipairs(cmdline_parse(cmdline_symbol():str_value())) do
+		if cmdline_matches(token, param) then
+			return true
+		end
+	end
+	return false
+end
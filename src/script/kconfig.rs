@@ -2,20 +2,94 @@ use bridge::Bridge;
 use std::path::Path;
 
 use anyhow::anyhow;
+use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
+use colored::Colorize;
+use itertools::Itertools;
 
 use crate::bridge;
 
 use super::Script;
+use flate2::read::GzDecoder;
 use std::fs;
+use std::io::Read;
 
 struct Assignment {
     symbol: String,
-    value: String,
+    /// The value without surrounding quotes, or None for `# CONFIG_X is not set` lines
+    value: Option<String>,
+    /// The value as written in the file
+    raw_value: String,
     line: usize,
 }
 
+/// Removes a trailing `# comment` from the given value, unless the # is quoted
+fn strip_trailing_comment(value: &str) -> &str {
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            '#' if !in_quotes => return value[..i].trim_end(),
+            _ => {}
+        }
+    }
+    value
+}
+
+/// The marker that precedes the gzip compressed config embedded by CONFIG_IKCONFIG
+const IKCONFIG_MARKER: &[u8] = b"IKCFG_ST";
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b, 0x08];
+
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn gunzip(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    GzDecoder::new(data).read_to_end(&mut out)?;
+    Ok(out)
+}
+
+/// Extracts the kernel config that was embedded into a kernel image by CONFIG_IKCONFIG.
+/// Like the kernel's scripts/extract-ikconfig, this works for an uncompressed vmlinux
+/// and for images that are themselves compressed with gzip.
+pub fn extract_ikconfig(image: impl AsRef<Path>) -> Result<String> {
+    let image = image.as_ref();
+    let data = fs::read(image).context(format!("Could not read kernel image {}", image.display()))?;
+
+    let find_config = |data: &[u8]| -> Option<Result<String>> {
+        let start = find_subslice(data, IKCONFIG_MARKER)? + IKCONFIG_MARKER.len();
+        Some(
+            gunzip(&data[start..])
+                .context("Could not decompress the embedded config")
+                .and_then(|config| String::from_utf8(config).context("The embedded config is not valid utf-8")),
+        )
+    };
+
+    if let Some(config) = find_config(&data) {
+        return config;
+    }
+
+    // The image may be compressed as a whole, so try every gzip stream in it
+    let mut offset = 0;
+    while let Some(pos) = find_subslice(&data[offset..], GZIP_MAGIC) {
+        let start = offset + pos;
+        if let Some(config) = gunzip(&data[start..]).ok().and_then(|inner| find_config(&inner)) {
+            return config;
+        }
+        offset = start + 1;
+    }
+
+    bail!(
+        "{} contains no embedded config. Either it was built without CONFIG_IKCONFIG, or it uses an unsupported compression",
+        image.display()
+    )
+}
+
 pub struct KConfig {
     filename: String,
     assignments: Vec<Assignment>,
@@ -30,32 +104,109 @@ impl KConfig {
         let mut assignments = Vec::new();
         for (i, line) in content.lines().enumerate() {
             let line = line.trim();
-            if line.is_empty() || line.starts_with('#') {
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(comment) = line.strip_prefix('#') {
+                // Keep track of "# CONFIG_X is not set", all other comments are ignored
+                if let Some(symbol) = comment
+                    .trim()
+                    .strip_suffix("is not set")
+                    .and_then(|s| s.trim().strip_prefix("CONFIG_"))
+                {
+                    assignments.push(Assignment {
+                        symbol: symbol.to_string(),
+                        value: None,
+                        raw_value: "n".to_string(),
+                        line: i + 1,
+                    });
+                }
                 continue;
             }
             let (k, v) = line.split_once('=').ok_or_else(|| anyhow!("invalid line {line}"))?;
+            let v = strip_trailing_comment(v.trim());
             assignments.push(Assignment {
                 symbol: k.trim().trim_start_matches("CONFIG_").to_string(),
-                value: v.trim().trim_matches('"').to_string(),
+                value: Some(v.trim_matches('"').to_string()),
+                raw_value: v.to_string(),
                 line: i + 1,
             });
         }
         Ok(KConfig { filename, assignments })
     }
+
+    /// Returns the canonical representation of this file: One assignment per line, sorted
+    /// by symbol name and always with the CONFIG_ prefix. Comments are removed except for
+    /// `# CONFIG_X is not set` lines. Repeated assignments keep their relative order.
+    pub fn format(&self) -> String {
+        let mut out = String::new();
+        for assignment in self.assignments.iter().sorted_by(|a, b| a.symbol.cmp(&b.symbol)) {
+            match assignment.value {
+                Some(_) => out.push_str(&format!("CONFIG_{}={}\n", assignment.symbol, assignment.raw_value)),
+                None => out.push_str(&format!("# CONFIG_{} is not set\n", assignment.symbol)),
+            }
+        }
+        out
+    }
+
+    /// Warns once if this file assigns =m to some symbols, while MODULES is neither
+    /// enabled already nor by this file. Otherwise, each of these assignments would fail
+    /// with the same root cause.
+    fn warn_modules_not_enabled(&self, bridge: &Bridge) {
+        let n_modules = self
+            .assignments
+            .iter()
+            .filter(|a| a.value.as_deref() == Some("m"))
+            .count();
+        if n_modules == 0
+            || self
+                .assignments
+                .iter()
+                .any(|a| a.symbol == "MODULES" && a.value.as_deref() == Some("y"))
+            || bridge
+                .symbol("MODULES")
+                .is_some_and(|s| s.get_tristate_value() == bridge::Tristate::Yes)
+        {
+            return;
+        }
+
+        bridge.count_warning();
+        eprintln!(
+            "{}: {} assigns =m to {} symbols, but MODULES is not enabled",
+            "warning".yellow().bold(),
+            self.filename,
+            n_modules
+        );
+        eprintln!(
+            "   {} {}",
+            "=".blue(),
+            "hint: enable MODULES before loading this file, otherwise all of these assignments will fail".dimmed()
+        );
+    }
 }
 
 impl Script for KConfig {
     fn apply(&self, bridge: &Bridge) -> Result<()> {
+        self.warn_modules_not_enabled(bridge);
         for assignment in &self.assignments {
-            bridge
-                .symbol(&assignment.symbol)
-                .with_context(|| format!("could not get symbol {:?}", assignment.symbol))?
+            // "is not set" comments are only tracked for formatting
+            let Some(value) = &assignment.value else {
+                continue;
+            };
+            let ret = bridge
+                .try_symbol(&assignment.symbol)
+                .with_context(|| format!("{}:{}", self.filename, assignment.line))?
                 .set_value_tracked(
-                    bridge::SymbolValue::Auto(assignment.value.clone()),
+                    bridge::SymbolValue::Auto(value.clone()),
                     self.filename.clone(),
                     assignment.line.try_into().unwrap(),
                     None,
-                )?;
+                );
+            // Errors are tracked in the history and reported later,
+            // unless we are supposed to stop at the first one.
+            if bridge.fail_fast {
+                ret?;
+            }
         }
         Ok(())
     }
@@ -1,14 +1,72 @@
 use super::{KConfig, Script};
 use crate::bridge::satisfier::SolverConfig;
-use crate::bridge::{Bridge, SymbolValue};
+use crate::bridge::types::CSymbol;
+use crate::bridge::{Bridge, Expr, SymbolValue, Terminal};
 
+use std::cell::RefCell;
 use std::fmt::Write;
 use std::fs;
 use std::path::Path;
 use std::result::Result::{Err as StdErr, Ok as StdOk};
 
 use anyhow::{Context, Ok, Result};
-use mlua::{self, Error as LuaError, ExternalResult, Lua};
+use mlua::{self, Error as LuaError, ExternalResult, Lua, Table};
+
+/// Errors from tracked assignments are recorded in the bridge's history and reported
+/// after the script has finished, so they are usually ignored here. If the bridge
+/// should fail fast, the error instead aborts script execution.
+fn check_fail_fast<T, E>(bridge: &Bridge, result: std::result::Result<T, E>) -> mlua::Result<()> {
+    match result {
+        StdErr(_) if bridge.fail_fast => StdErr(LuaError::RuntimeError(
+            "aborting after the first failed assignment (--fail-fast)".to_string(),
+        )),
+        _ => StdOk(()),
+    }
+}
+
+/// Converts an expression into a nested lua table. Inner nodes have the form
+/// `{op="and", left=..., right=...}`, `{op="or", ...}` or `{op="not", expr=...}`,
+/// comparisons are `{op="eq", left=..., right=...}` (and neq, lth, leq, gth, geq),
+/// and leaves are `{op="sym", name="FOO"}` or `{op="const", value=true}`.
+fn expr_to_table<'lua>(lua: &'lua Lua, expr: &Expr) -> mlua::Result<Table<'lua>> {
+    let sym_table = |symbol: &*mut CSymbol| -> mlua::Result<Table<'lua>> {
+        let table = lua.create_table()?;
+        table.set("op", "sym")?;
+        table.set("name", unsafe { &**symbol }.name().map(|n| n.to_string()))?;
+        StdOk(table)
+    };
+    let binary = |op: &str, left: Table<'lua>, right: Table<'lua>| -> mlua::Result<Table<'lua>> {
+        let table = lua.create_table()?;
+        table.set("op", op)?;
+        table.set("left", left)?;
+        table.set("right", right)?;
+        StdOk(table)
+    };
+
+    match expr {
+        Expr::Const(value) => {
+            let table = lua.create_table()?;
+            table.set("op", "const")?;
+            table.set("value", *value)?;
+            StdOk(table)
+        }
+        Expr::And(a, b) => binary("and", expr_to_table(lua, a)?, expr_to_table(lua, b)?),
+        Expr::Or(a, b) => binary("or", expr_to_table(lua, a)?, expr_to_table(lua, b)?),
+        Expr::Not(a) => {
+            let table = lua.create_table()?;
+            table.set("op", "not")?;
+            table.set("expr", expr_to_table(lua, a)?)?;
+            StdOk(table)
+        }
+        Expr::Terminal(Terminal::Symbol(s)) => sym_table(s),
+        Expr::Terminal(Terminal::Eq(a, b)) => binary("eq", sym_table(a)?, sym_table(b)?),
+        Expr::Terminal(Terminal::Neq(a, b)) => binary("neq", sym_table(a)?, sym_table(b)?),
+        Expr::Terminal(Terminal::Lth(a, b)) => binary("lth", sym_table(a)?, sym_table(b)?),
+        Expr::Terminal(Terminal::Leq(a, b)) => binary("leq", sym_table(a)?, sym_table(b)?),
+        Expr::Terminal(Terminal::Gth(a, b)) => binary("gth", sym_table(a)?, sym_table(b)?),
+        Expr::Terminal(Terminal::Geq(a, b)) => binary("geq", sym_table(a)?, sym_table(b)?),
+    }
+}
 
 pub struct LuaScript {
     lua: Lua,
@@ -35,25 +93,30 @@ impl LuaScript {
 
 impl Script for LuaScript {
     fn apply(&self, bridge: &Bridge) -> Result<()> {
-        self.lua.scope(|scope| {
+        // Saved states of nested ak.with blocks, together with the length of the history
+        // and the pending weak assignments at that time
+        let saved_states = RefCell::new(Vec::new());
+        let ret = self.lua.scope(|scope| {
             let symbol_set_auto = scope.create_function(
                 |_, (name, value, file, line, traceback): (String, String, String, u32, String)| {
-                    bridge
-                        .symbol(&name)
-                        .unwrap()
-                        .set_value_tracked(SymbolValue::Auto(value), file, line, Some(traceback))
-                        .ok();
-                    StdOk(())
+                    let ret = bridge.symbol(&name).unwrap().set_value_tracked(
+                        SymbolValue::Auto(value),
+                        file,
+                        line,
+                        Some(traceback),
+                    );
+                    check_fail_fast(bridge, ret)
                 },
             )?;
             let symbol_set_bool = scope.create_function(
                 |_, (name, value, file, line, traceback): (String, bool, String, u32, String)| {
-                    bridge
-                        .symbol(&name)
-                        .unwrap()
-                        .set_value_tracked(SymbolValue::Boolean(value), file, line, Some(traceback))
-                        .ok();
-                    StdOk(())
+                    let ret = bridge.symbol(&name).unwrap().set_value_tracked(
+                        SymbolValue::Boolean(value),
+                        file,
+                        line,
+                        Some(traceback),
+                    );
+                    check_fail_fast(bridge, ret)
                 },
             )?;
             let symbol_set_number = scope.create_function(
@@ -65,29 +128,52 @@ impl Script for LuaScript {
                             "Please pass values >=2*63 in string syntax. lua doesn't support this.".to_string(),
                         ));
                     }
-                    bridge
-                        .symbol(&name)
-                        .unwrap()
-                        .set_value_tracked(SymbolValue::Number(value as u64), file, line, Some(traceback))
-                        .ok();
-                    StdOk(())
+                    let ret = bridge.symbol(&name).unwrap().set_value_tracked(
+                        SymbolValue::Number(value as u64),
+                        file,
+                        line,
+                        Some(traceback),
+                    );
+                    check_fail_fast(bridge, ret)
                 },
             )?;
             let symbol_set_tristate = scope.create_function(
                 |_, (name, value, file, line, traceback): (String, String, String, u32, String)| {
-                    bridge
-                        .symbol(&name)
-                        .unwrap()
-                        .set_value_tracked(
+                    let ret =
+                        bridge.symbol(&name).unwrap().set_value_tracked(
                             SymbolValue::Tristate(value.parse().map_err(|_| {
                                 LuaError::RuntimeError(format!("Could not convert {value} to tristate"))
                             })?),
                             file,
                             line,
                             Some(traceback),
-                        )
-                        .ok();
-                    StdOk(())
+                        );
+                    check_fail_fast(bridge, ret)
+                },
+            )?;
+            let symbol_set_auto_weak = scope.create_function(
+                |_, (name, value, file, line, traceback): (String, String, String, u32, String)| {
+                    let ret = bridge.symbol(&name).unwrap().set_value_weak_tracked(
+                        SymbolValue::Auto(value),
+                        file,
+                        line,
+                        Some(traceback),
+                    );
+                    let set = matches!(ret, StdOk(true));
+                    check_fail_fast(bridge, ret)?;
+                    StdOk(set)
+                },
+            )?;
+            let symbol_append_string = scope.create_function(
+                |_, (name, suffix, separator, file, line, traceback): (String, String, String, String, u32, String)| {
+                    let ret = bridge.symbol(&name).unwrap().append_string_tracked(
+                        &suffix,
+                        &separator,
+                        file,
+                        line,
+                        Some(traceback),
+                    );
+                    check_fail_fast(bridge, ret)
                 },
             )?;
             let symbol_satisfy_and_set = scope.create_function(
@@ -109,47 +195,72 @@ impl Script for LuaScript {
 
                     // If there was an error, it will have been tracked already.
                     // Ignore and continue.
-                    if satisfying_configuration.is_err() {
-                        return StdOk(());
-                    }
+                    let satisfying_configuration = match satisfying_configuration {
+                        StdOk(satisfying_configuration) => satisfying_configuration,
+                        err => return check_fail_fast(bridge, err),
+                    };
 
-                    for (sym, value) in satisfying_configuration.unwrap() {
-                        bridge
-                            .symbol(&sym)
-                            .unwrap()
-                            .set_value_tracked(
-                                SymbolValue::Tristate(value),
-                                file.clone(),
-                                line,
-                                Some(traceback.clone()),
-                            )
-                            .ok();
+                    for (sym, value) in satisfying_configuration {
+                        let ret = bridge.symbol(&sym).unwrap().set_value_tracked(
+                            SymbolValue::Tristate(value),
+                            file.clone(),
+                            line,
+                            Some(traceback.clone()),
+                        );
+                        check_fail_fast(bridge, ret)?;
                     }
 
                     let mut symbol = bridge.symbol(&name).unwrap();
                     if symbol.prompt_count() > 0 {
-                        symbol
-                            .set_value_tracked(SymbolValue::Tristate(value), file, line, Some(traceback))
-                            .ok();
+                        let ret = symbol.set_value_tracked(SymbolValue::Tristate(value), file, line, Some(traceback));
+                        check_fail_fast(bridge, ret)?;
                     }
 
                     StdOk(())
                 },
             )?;
-            let symbol_get_string =
-                scope.create_function(|_, name: String| StdOk(bridge.symbol(&name).unwrap().get_string_value()))?;
+            let symbol_get_string = scope.create_function(|_, name: String| {
+                let value = bridge.symbol(&name).unwrap().get_string_value();
+                bridge.read_set.borrow_mut().symbols.insert(name);
+                StdOk(value)
+            })?;
+            let symbol_is_visible = scope.create_function(|_, (name, log_skipped): (String, bool)| {
+                let symbol = bridge.symbol(&name).unwrap();
+                let visible = symbol.is_visible();
+                if !visible && log_skipped {
+                    symbol.log_skipped_invisible();
+                }
+                bridge.read_set.borrow_mut().symbols.insert(name);
+                StdOk(visible)
+            })?;
+            let symbol_exists = scope.create_function(|_, name: String| {
+                let name = name.trim_start_matches("CONFIG_").to_string();
+                let exists = bridge.symbol(&name).is_some();
+                bridge.read_set.borrow_mut().symbols.insert(name);
+                StdOk(exists)
+            })?;
             let symbol_get_type = scope.create_function(|_, name: String| {
-                StdOk(format!("{:?}", bridge.symbol(&name).unwrap().symbol_type()))
+                let symbol_type = format!("{:?}", bridge.symbol(&name).unwrap().symbol_type());
+                bridge.read_set.borrow_mut().symbols.insert(name);
+                StdOk(symbol_type)
+            })?;
+
+            let symbol_deps = scope.create_function(|lua, name: String| {
+                let expr = bridge
+                    .try_symbol(&name)
+                    .map_err(|e| LuaError::RuntimeError(e.to_string()))?
+                    .visibility_expression()
+                    .map_err(|e| LuaError::RuntimeError(format!("Could not convert dependencies of {name}: {e}")))?;
+                expr_to_table(lua, &expr)
             })?;
 
             let load_kconfig = scope.create_function(|_, (path, checked): (String, bool)| {
                 if checked {
-                    KConfig::new(path)
+                    let ret = KConfig::new(path)
                         .map_err(|e| LuaError::RuntimeError(e.to_string()))?
-                        .apply(bridge)
-                        .ok();
+                        .apply(bridge);
                     // Errors will be tracked automatically
-                    StdOk(())
+                    check_fail_fast(bridge, ret)
                 } else {
                     bridge
                         .read_config_unchecked(path)
@@ -157,19 +268,61 @@ impl Script for LuaScript {
                 }
             })?;
 
-            let kernel_env = scope.create_function(|_, name: String| StdOk(bridge.get_env(&name)))?;
+            let push_state = scope.create_function(|_, ()| {
+                let snapshot = bridge.snapshot().map_err(|e| LuaError::RuntimeError(e.to_string()))?;
+                saved_states.borrow_mut().push((
+                    snapshot,
+                    bridge.history.borrow().len(),
+                    bridge.weak_assignments.borrow().clone(),
+                ));
+                StdOk(())
+            })?;
+            let pop_state = scope.create_function(|_, ()| {
+                let (snapshot, history_len, weak_assignments) = saved_states
+                    .borrow_mut()
+                    .pop()
+                    .ok_or_else(|| LuaError::RuntimeError("no saved state to restore".to_string()))?;
+                bridge.history.borrow_mut().truncate(history_len);
+                *bridge.weak_assignments.borrow_mut() = weak_assignments;
+                bridge
+                    .restore(&snapshot)
+                    .map_err(|e| LuaError::RuntimeError(e.to_string()))
+            })?;
+            let symbol_set_temporary = scope.create_function(|_, (name, value): (String, String)| {
+                bridge
+                    .try_symbol(&name)
+                    .map_err(|e| LuaError::RuntimeError(e.to_string()))?
+                    .set_value(SymbolValue::Auto(value.clone()))
+                    .map_err(|e| LuaError::RuntimeError(format!("could not temporarily set {name}={value}: {e}")))
+            })?;
+
+            let kernel_env = scope.create_function(|_, name: String| {
+                let value = bridge.get_env(&name);
+                bridge.read_set.borrow_mut().env.insert(name);
+                StdOk(value)
+            })?;
 
             let ak = self.lua.create_table()?;
             ak.set("kernel_dir", bridge.kernel_dir.to_str())?;
+            // The kernel version is always exposed to scripts
             ak.set("kernel_version_str", bridge.get_env("KERNELVERSION"))?;
+            bridge.read_set.borrow_mut().env.insert("KERNELVERSION".to_string());
             ak.set("symbol_set_auto", symbol_set_auto)?;
             ak.set("symbol_set_bool", symbol_set_bool)?;
             ak.set("symbol_set_number", symbol_set_number)?;
             ak.set("symbol_set_tristate", symbol_set_tristate)?;
+            ak.set("symbol_set_auto_weak", symbol_set_auto_weak)?;
+            ak.set("symbol_append_string", symbol_append_string)?;
             ak.set("symbol_satisfy_and_set", symbol_satisfy_and_set)?;
             ak.set("symbol_get_string", symbol_get_string)?;
             ak.set("symbol_get_type", symbol_get_type)?;
+            ak.set("symbol_is_visible", symbol_is_visible)?;
+            ak.set("symbol_exists", symbol_exists)?;
+            ak.set("symbol_deps", symbol_deps)?;
             ak.set("load_kconfig", load_kconfig)?;
+            ak.set("push_state", push_state)?;
+            ak.set("pop_state", pop_state)?;
+            ak.set("symbol_set_temporary", symbol_set_temporary)?;
             ak.set("kernel_env", kernel_env)?;
             self.lua.globals().set("ak", ak)?;
 
@@ -192,8 +345,14 @@ impl Script for LuaScript {
 
             self.lua.load(&self.code).set_name(&self.filename).exec()?;
             core::result::Result::Ok(())
-        })?;
+        });
+        if let StdErr(err) = ret {
+            bridge.weak_assignments.borrow_mut().clear();
+            return Err(err.into());
+        }
 
+        // Weak assignments yield to all explicit ones, regardless of their order in the script
+        bridge.apply_weak_assignments()?;
         Ok(())
     }
 }
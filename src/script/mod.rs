@@ -6,7 +6,7 @@ use anyhow::{bail, Ok, Result};
 use std::path::Path;
 
 use colored::Colorize;
-pub use kconfig::KConfig;
+pub use kconfig::{extract_ikconfig, KConfig};
 pub use lua::LuaScript;
 
 pub trait Script {
@@ -1,8 +1,13 @@
 use anyhow::Result;
 use autokernel::{
-    bridge::{Bridge, SymbolValue, Tristate},
-    script::{KConfig, LuaScript, Script},
+    bridge::satisfier::SolverConfig,
+    bridge::types::SymbolFlags,
+    bridge::{validate_transactions, AtomicApply, Bridge, SnapshotBridge, SymbolValue, Tristate},
+    script::{extract_ikconfig, KConfig, LuaScript, Script},
 };
+use flate2::{write::GzEncoder, Compression};
+use std::fs;
+use std::io::Write;
 
 mod setup_teardown;
 use serial_test::serial;
@@ -88,11 +93,390 @@ fn integration_test_luaconfig() {
     "#
     );
     lua_test!("test_full_config", include_str!("good.lua"));
-
+    lua_test!(
+        "cmdline",
+        r#"
+        CMDLINE_BOOL "y"
+        CMDLINE "quiet root=/dev/sda1 debug"
+        ak.cmdline_add "root=/dev/sda2"
+        ak.cmdline_add "nosmt"
+        ak.cmdline_remove "debug"
+        assert(CMDLINE:value() == "quiet root=/dev/sda2 nosmt")
+        assert(ak.cmdline_has "root")
+        assert(not ak.cmdline_has "root=/dev/sda1")
+        assert(not ak.cmdline_has "debug")
+    "#
+    );
+    lua_test!(
+        "set_if_visible",
+        r#"
+        assert(CMDLINE_BOOL:set_if_visible "y")
+        assert(CMDLINE_BOOL:value() == y)
+        -- Cannot be set manually, so this must be skipped without an error
+        assert(not RTLWIFI_USB:is_visible())
+        assert(not RTLWIFI_USB:set_if_visible "y")
+    "#
+    );
+    lua_test!(
+        "with",
+        r#"
+        CMDLINE_BOOL "n"
+        local visible = ak.with({ CMDLINE_BOOL = y }, function()
+            CMDLINE "quiet"
+            return CMDLINE:is_visible()
+        end)
+        assert(visible)
+        assert(CMDLINE_BOOL:value() == n)
+        assert(not CMDLINE:is_visible())
+    "#
+    );
+    lua_test!(
+        "set_at",
+        r#"
+        ak.set_at("CONFIG_CMDLINE_BOOL", y, "helper.lua", 42)
+        assert(CMDLINE_BOOL:value() == y)
+    "#
+    );
+    assert!(bridge
+        .history
+        .borrow()
+        .iter()
+        .any(|t| t.symbol == "CMDLINE_BOOL" && t.file == "helper.lua" && t.line == 42));
+    lua_test!(
+        "symbol_exists",
+        r#"
+        assert(ak.symbol_exists("MODULES"))
+        assert(ak.symbol_exists("CONFIG_MODULES"))
+        assert(not ak.symbol_exists("DOES_NOT_EXIST"))
+    "#
+    );
     lua_bad_test!("bad_literal", "CONFIG_CRYPTO y");
     teardown();
 }
 
+#[test]
+#[serial(K)]
+fn integration_test_weak() {
+    let bridge = setup();
+    let script = LuaScript::from_raw(
+        "weak.lua".into(),
+        r#"
+        -- A strong assignment before the weak one
+        IKCONFIG(n)
+        assert(not ak.symbol_set_weak("CONFIG_IKCONFIG", y))
+        -- A weak assignment before the strong one
+        assert(IKHEADERS:set_weak(m))
+        IKHEADERS(n)
+        -- A weak assignment before a strong one with the same value
+        assert(CMDLINE_BOOL:set_weak(y))
+        CMDLINE_BOOL(y)
+        -- Only weakly assigned, so it is applied once the script finished
+        assert(MODULES:set_weak(y))
+    "#
+        .into(),
+    )
+    .unwrap();
+    script.apply(&bridge).unwrap();
+
+    let value = |name| bridge.symbol(name).unwrap().get_value().unwrap();
+    assert_eq!(value("IKCONFIG"), SymbolValue::Boolean(false));
+    assert_eq!(value("IKHEADERS"), SymbolValue::Tristate(Tristate::No));
+    assert_eq!(value("CMDLINE_BOOL"), SymbolValue::Boolean(true));
+    assert_eq!(value("MODULES"), SymbolValue::Boolean(true));
+    // Only the weak assignment without an explicit one was applied
+    let weak = bridge
+        .history
+        .borrow()
+        .iter()
+        .filter(|t| t.weak)
+        .map(|t| t.symbol.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(weak, ["MODULES"]);
+    validate_transactions(&bridge.history.borrow(), &bridge).unwrap();
+    assert_eq!(bridge.n_warnings.get(), 0);
+    teardown();
+}
+
+#[test]
+#[serial(K)]
+fn integration_test_weak_allowlist() {
+    let mut bridge = setup();
+    bridge.allowlist = Some(["MODULES".to_string()].into());
+    // Weak assignments that are filtered by the allowlist must not report success
+    let script = LuaScript::from_raw(
+        "weak_allowlist.lua".into(),
+        r#"
+        assert(not IKCONFIG:set_weak(y))
+        assert(not ak.symbol_set_weak("CONFIG_IKCONFIG", y))
+    "#
+        .into(),
+    )
+    .unwrap();
+    script.apply(&bridge).unwrap();
+    assert!(!bridge.history.borrow().iter().any(|t| t.symbol == "IKCONFIG"));
+    teardown();
+}
+
+#[test]
+#[serial(K)]
+fn integration_test_count_warnings() {
+    let bridge = setup();
+    let config = KConfig::from_content(
+        "reassign.kconfig".into(),
+        "CONFIG_CMDLINE_BOOL=y\nCONFIG_CMDLINE_BOOL=n\nCONFIG_CMDLINE_BOOL=n\n".into(),
+    )
+    .unwrap();
+    config.apply(&bridge).unwrap();
+    validate_transactions(&bridge.history.borrow(), &bridge).unwrap();
+    // Assigning the same value again does not change anything and is not reported
+    assert_eq!(bridge.n_warnings.get(), 1);
+    bridge.fresh_config().unwrap();
+    assert_eq!(bridge.n_warnings.get(), 0);
+    teardown();
+}
+
+#[test]
+#[serial(K)]
+fn integration_test_apply_atomic() {
+    let bridge = setup();
+    let before = bridge.symbol("CMDLINE_BOOL").unwrap().get_value().unwrap();
+    let target = if before == SymbolValue::Boolean(true) { "n" } else { "y" };
+
+    // The second assignment is invalid, so the first one must be rolled back
+    let config = KConfig::from_content(
+        "atomic.kconfig".into(),
+        format!("CONFIG_CMDLINE_BOOL={target}\nCONFIG_MODULES=maybe\n"),
+    )
+    .unwrap();
+    match bridge.apply_atomic(&config).unwrap() {
+        AtomicApply::RolledBack { history, error } => {
+            assert!(error.is_none());
+            assert_eq!(history.len(), 2);
+            assert!(history[1].error.is_some());
+        }
+        AtomicApply::Applied => panic!("invalid script was applied"),
+    }
+    assert_eq!(bridge.symbol("CMDLINE_BOOL").unwrap().get_value().unwrap(), before);
+
+    let config = KConfig::from_content("atomic.kconfig".into(), format!("CONFIG_CMDLINE_BOOL={target}\n")).unwrap();
+    assert!(matches!(bridge.apply_atomic(&config).unwrap(), AtomicApply::Applied));
+    assert_ne!(bridge.symbol("CMDLINE_BOOL").unwrap().get_value().unwrap(), before);
+
+    // Reads made by a rolled back script must not leak into the read set
+    let read_set = bridge.read_set.borrow().symbols.clone();
+    let script = LuaScript::from_raw(
+        "atomic.lua".into(),
+        "ak.symbol_exists(\"CONFIG_IKCONFIG\")\nCONFIG_MODULES \"maybe\"\n".into(),
+    )
+    .unwrap();
+    assert!(matches!(
+        bridge.apply_atomic(&script).unwrap(),
+        AtomicApply::RolledBack { .. }
+    ));
+    assert_eq!(bridge.read_set.borrow().symbols, read_set);
+    teardown();
+}
+
+#[test]
+#[serial(K)]
+fn integration_test_dependency_closure() {
+    let bridge = setup();
+    // WLAN_VENDOR_REALTEK is located in the WLAN menu, which requires NETDEVICES and NET
+    let full = bridge.dependency_closure("WLAN_VENDOR_REALTEK", None);
+    for symbol in ["WLAN", "NETDEVICES", "NET"] {
+        assert!(full.contains(symbol), "{symbol} missing in {full:?}");
+    }
+    assert!(!full.contains("WLAN_VENDOR_REALTEK"));
+
+    let direct = bridge.dependency_closure("WLAN_VENDOR_REALTEK", Some(1));
+    assert!(direct.contains("WLAN"));
+    assert!(direct.is_subset(&full));
+
+    assert!(bridge.dependency_closure("WLAN_VENDOR_REALTEK", Some(0)).is_empty());
+    assert!(bridge.dependency_closure("DOES_NOT_EXIST", None).is_empty());
+    teardown();
+}
+
 fn test_script(bridge: &Bridge, script: &impl Script) -> Result<()> {
     script.apply(bridge)
 }
+
+#[test]
+#[serial(K)]
+fn integration_test_export_snapshot() {
+    let bridge = setup();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("snapshot.json");
+    bridge.export_snapshot(&path).unwrap();
+
+    let snapshot = SnapshotBridge::load(&path).unwrap();
+    assert_eq!(snapshot.kernel_version(), bridge.get_env("KERNELVERSION").as_deref());
+    for name in ["MODULES", "DEFAULT_HOSTNAME", "WLAN_VENDOR_REALTEK"] {
+        let symbol = bridge.symbol(name).unwrap();
+        assert_eq!(
+            snapshot.get_string_value(name),
+            Some(symbol.get_string_value().as_str())
+        );
+        assert_eq!(snapshot.symbol_type(name), Some(symbol.symbol_type()));
+    }
+    assert!(snapshot
+        .dependencies("WLAN_VENDOR_REALTEK")
+        .unwrap()
+        .contains(&"WLAN".to_string()));
+    assert!(snapshot.symbol("THIS_SYMBOL_DOES_NOT_EXIST").is_none());
+    teardown();
+}
+
+#[test]
+#[serial(K)]
+fn integration_test_satisfy_plan() {
+    let bridge = setup();
+    let symbol = bridge.symbol("WLAN_VENDOR_REALTEK").unwrap();
+    let plan = symbol.satisfy_plan(SolverConfig::default()).unwrap();
+    assert_eq!(plan.target, "WLAN_VENDOR_REALTEK");
+    if symbol.visible() == Tristate::No {
+        assert!(!plan.is_empty());
+    }
+    // Without recursion, all steps are required by the target itself
+    assert!(plan
+        .steps
+        .iter()
+        .all(|s| s.direct && s.required_for == "WLAN_VENDOR_REALTEK"));
+
+    let mut flat = symbol.satisfy(SolverConfig::default()).unwrap();
+    let mut assignments = plan.assignments();
+    flat.sort();
+    assignments.sort();
+    assert_eq!(flat, assignments);
+    teardown();
+}
+
+#[test]
+fn test_extract_ikconfig() {
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    let config = "CONFIG_MODULES=y\n# CONFIG_DEBUG_KERNEL is not set\n";
+    let mut vmlinux = b"\x7fELF some kernel code IKCFG_ST".to_vec();
+    vmlinux.extend(gzip(config.as_bytes()));
+    vmlinux.extend(b"IKCFG_ED more kernel code");
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("vmlinux");
+    fs::write(&path, &vmlinux).unwrap();
+    assert_eq!(extract_ikconfig(&path).unwrap(), config);
+
+    // A compressed image with a preceding header
+    let mut vmlinuz = b"boot header".to_vec();
+    vmlinuz.extend(gzip(&vmlinux));
+    fs::write(&path, &vmlinuz).unwrap();
+    assert_eq!(extract_ikconfig(&path).unwrap(), config);
+
+    fs::write(&path, b"no config in here").unwrap();
+    assert!(extract_ikconfig(&path).is_err());
+}
+
+#[test]
+#[serial(K)]
+fn integration_test_write_config_error() {
+    let bridge = setup();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("does-not-exist").join(".config");
+    let err = bridge.write_config(&path).unwrap_err();
+    assert!(err.to_string().contains(&path.display().to_string()));
+    // The underlying OS error must be reported
+    assert!(err.chain().any(|e| e.downcast_ref::<std::io::Error>().is_some()));
+
+    // Permissions are not enforced for root, so this case can only be checked as a regular user
+    if unsafe { libc::geteuid() } != 0 {
+        use std::os::unix::fs::PermissionsExt;
+        let readonly = dir.path().join("readonly");
+        std::fs::create_dir(&readonly).unwrap();
+        std::fs::set_permissions(&readonly, std::fs::Permissions::from_mode(0o555)).unwrap();
+        let path = readonly.join(".config");
+        let err = bridge.write_config(&path).unwrap_err();
+        assert!(err.to_string().contains(&path.display().to_string()));
+        assert!(err
+            .chain()
+            .filter_map(|e| e.downcast_ref::<std::io::Error>())
+            .any(|e| e.kind() == std::io::ErrorKind::PermissionDenied));
+        std::fs::set_permissions(&readonly, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    teardown();
+}
+
+#[test]
+#[serial(K)]
+fn integration_test_fresh_config() {
+    let bridge = setup();
+    bridge.fresh_config().unwrap();
+    let defaults = bridge.symbol_values();
+
+    let first = KConfig::from_content("first.kconfig".into(), "CONFIG_CMDLINE_BOOL=y\n".into()).unwrap();
+    first.apply(&bridge).unwrap();
+    assert_eq!(
+        bridge.symbol("CMDLINE_BOOL").unwrap().get_value().unwrap(),
+        SymbolValue::Boolean(true)
+    );
+
+    bridge.fresh_config().unwrap();
+    assert!(bridge.history.borrow().is_empty());
+    assert_eq!(bridge.symbol_values(), defaults);
+
+    let second = KConfig::from_content("second.kconfig".into(), "CONFIG_DEFAULT_HOSTNAME=\"fresh\"\n".into()).unwrap();
+    second.apply(&bridge).unwrap();
+    assert_eq!(
+        bridge.symbol("CMDLINE_BOOL").unwrap().get_value().unwrap(),
+        defaults["CMDLINE_BOOL"]
+    );
+    assert_eq!(bridge.history.borrow().len(), 1);
+    teardown();
+}
+
+#[test]
+#[serial(K)]
+fn integration_test_read_config() {
+    let bridge = setup();
+    bridge.fresh_config().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("net.config");
+    // WLAN is not set, but defaults to y once its dependencies are enabled
+    fs::write(&path, "CONFIG_NET=y\nCONFIG_NETDEVICES=y\n").unwrap();
+    bridge.read_config(&path, true).unwrap();
+    assert_eq!(bridge.symbol("NETDEVICES").unwrap().get_tristate_value(), Tristate::Yes);
+    assert_eq!(bridge.symbol("WLAN").unwrap().get_tristate_value(), Tristate::Yes);
+    bridge.fresh_config().unwrap();
+    teardown();
+}
+
+#[test]
+#[serial(K)]
+fn integration_test_symbol_location() {
+    let bridge = setup();
+    let (file, line) = bridge.symbol("MODULES").unwrap().location().unwrap();
+    assert_eq!(file, bridge.kernel_dir.join("init/Kconfig"));
+    let definition = fs::read_to_string(&file)
+        .unwrap()
+        .lines()
+        .nth(line as usize - 1)
+        .unwrap()
+        .to_string();
+    assert!(definition.starts_with("menuconfig MODULES"));
+    teardown();
+}
+
+#[test]
+#[serial(K)]
+fn integration_test_symbols_with_flag() {
+    let bridge = setup();
+    let choice_values = bridge.symbols_with_flag(SymbolFlags::CHOICEVAL);
+    assert!(choice_values.iter().any(|s| s.name().as_deref() == Some("KERNEL_GZIP")));
+    assert!(choice_values
+        .iter()
+        .all(|s| s.flags().intersects(SymbolFlags::CHOICEVAL)));
+    assert!(!choice_values.iter().any(|s| s.name().as_deref() == Some("MODULES")));
+    teardown();
+}
@@ -3,28 +3,125 @@ use autokernel::bridge::Bridge;
 use anyhow::Context;
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
 
 const TMP_TEST_DIR: &str = "autokernel-test";
 const TEST_KERNEL: &str = "linux-5.19.1";
 
-fn cache_kernel(kdir: &PathBuf) -> String {
+/// Set this to the path of a local kernel tarball to skip the download, e.g. for offline testing
+const LOCAL_TARBALL_ENV: &str = "AUTOKERNEL_TEST_KERNEL_TARBALL";
+const KERNEL_MIRROR: &str = "https://cdn.kernel.org/pub/linux/kernel/v5.x";
+const DOWNLOAD_ATTEMPTS: u32 = 3;
+/// The published checksums of all files on the kernel mirror
+const CHECKSUMS_FILE: &str = "sha256sums.asc";
+
+/// Downloads the given url to dest, retrying with an increasing delay on failure.
+/// The file is only moved to dest once the download succeeded completely.
+fn download(url: &str, dest: &Path) -> bool {
+    let partial = dest.with_extension("part");
+    for attempt in 1..=DOWNLOAD_ATTEMPTS {
+        let ok = Command::new("wget")
+            .arg("-q")
+            .arg("-O")
+            .arg(&partial)
+            .arg(url)
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(false);
+        if ok {
+            fs::rename(&partial, dest).unwrap();
+            return true;
+        }
+        let _ = fs::remove_file(&partial);
+        if attempt < DOWNLOAD_ATTEMPTS {
+            let delay = Duration::from_secs(2u64.pow(attempt));
+            eprintln!("download of {url} failed (attempt {attempt}/{DOWNLOAD_ATTEMPTS}), retrying in {delay:?}");
+            thread::sleep(delay);
+        }
+    }
+    false
+}
+
+fn sha256(file: &Path) -> String {
+    let output = Command::new("sha256sum").arg(file).output().unwrap();
+    assert!(
+        output.status.success(),
+        "could not compute checksum of {}",
+        file.display()
+    );
+    String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .next()
+        .unwrap()
+        .to_string()
+}
+
+/// Returns the sha256 checksum of the given file as published by kernel.org. The checksums
+/// are cached next to the kernel tarball, so for offline testing it suffices to place
+/// sha256sums.asc in the test directory once.
+fn expected_sha256(kdir: &Path, file_name: &str) -> String {
+    let sums = kdir.join(CHECKSUMS_FILE);
+    if !sums.exists() {
+        assert!(
+            download(&format!("{KERNEL_MIRROR}/{CHECKSUMS_FILE}"), &sums),
+            "could not download kernel checksums, place {CHECKSUMS_FILE} from {KERNEL_MIRROR} in {} instead",
+            kdir.display()
+        );
+    }
+    fs::read_to_string(&sums)
+        .unwrap()
+        .lines()
+        .find_map(|line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [sum, name] if name == file_name => Some(sum.to_string()),
+            _ => None,
+        })
+        .unwrap_or_else(|| panic!("no checksum for {file_name} in {}", sums.display()))
+}
+
+/// Panics if the given kernel tarball does not match its published checksum.
+/// A mismatching file is removed if `remove` is set, so that it is downloaded again next time.
+fn verify_kernel(kdir: &Path, kernel_tar: &Path, remove: bool) {
+    let expected = expected_sha256(kdir, &format!("{TEST_KERNEL}.tar.xz"));
+    let actual = sha256(kernel_tar);
+    if actual != expected {
+        if remove {
+            fs::remove_file(kernel_tar).unwrap();
+        }
+        panic!(
+            "checksum mismatch for {}: expected {expected}, got {actual}",
+            kernel_tar.display()
+        );
+    }
+}
+
+fn cache_kernel(kdir: &Path) -> PathBuf {
+    if let Ok(tarball) = env::var(LOCAL_TARBALL_ENV) {
+        println!("using local kernel tar {tarball}");
+        let tarball =
+            fs::canonicalize(&tarball).unwrap_or_else(|e| panic!("invalid {LOCAL_TARBALL_ENV} {tarball}: {e}"));
+        verify_kernel(kdir, &tarball, false);
+        return tarball;
+    }
+
     // latest="$(curl -s https://www.kernel.org/ | grep -A1 'stable:' | grep -oP '(?<=strong>).*(?=</strong.*)' | head -1)"
-    let kernel_tar = format!("{}.tar.xz", &TEST_KERNEL);
+    let kernel_tar_name = format!("{}.tar.xz", &TEST_KERNEL);
+    let kernel_tar = kdir.join(&kernel_tar_name);
     // test if kernel exists
-    if kdir.join(&kernel_tar).exists() {
+    if kernel_tar.exists() {
         println!("kernel tar already in cache");
+        verify_kernel(kdir, &kernel_tar, true);
         return kernel_tar;
     }
 
     println!("downloading kernel {} ...", TEST_KERNEL);
-    Command::new("wget")
-        .arg("-q")
-        .arg(format!("https://cdn.kernel.org/pub/linux/kernel/v5.x/{}", kernel_tar))
-        .current_dir(kdir)
-        .status()
-        .unwrap();
+    assert!(
+        download(&format!("{KERNEL_MIRROR}/{kernel_tar_name}"), &kernel_tar),
+        "could not download kernel {TEST_KERNEL}, set {LOCAL_TARBALL_ENV} to use a local tarball instead"
+    );
+    verify_kernel(kdir, &kernel_tar, true);
     kernel_tar
 }
 
@@ -48,13 +145,17 @@ fn setup_kernel(kdir: &PathBuf) -> PathBuf {
     //}
 
     println!("extracting kernel {} ...", TEST_KERNEL);
-    Command::new("tar")
+    let status = Command::new("tar")
         .arg("-xvf")
         .arg(&kernel_tar)
         .current_dir(kdir)
         .stdout(Stdio::null())
         .status()
         .unwrap();
+    if !status.success() {
+        let _ = fs::remove_dir_all(&res);
+        panic!("could not extract {}", kernel_tar.display());
+    }
     res
 }
 